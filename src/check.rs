@@ -0,0 +1,483 @@
+// Checks of a compiled game that find mistakes in the script before anyone plays it.
+//
+// Each check returns diagnostics that point at the token they are about, so they can be shown
+// with the line of the script they come from (see Diagnostic::render).
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::path::Path;
+use super::{Expr, Game, GameAction, GameAudio, GameItem, GameRoom, InventoryAction, InventoryKind, ParseNode, Program, Token, TokenKind,
+            interactable_noun, inventory_kind_to_string, is_keyword, section_rank};
+
+// The room every game starts in.
+pub const START_ROOM: &str = "init";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+  // The game can run into it, ie a goto to a room that does not exist.
+  Error,
+  // Legal, but most likely not what the author meant.
+  Warning,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+  pub severity: Severity,
+  pub message: String,
+  pub token: Token,
+}
+
+impl Diagnostic {
+  pub fn error(message: String, token: &Token) -> Diagnostic {
+    Diagnostic { severity: Severity::Error, message, token: token.clone() }
+  }
+
+  pub fn warning(message: String, token: &Token) -> Diagnostic {
+    Diagnostic { severity: Severity::Warning, message, token: token.clone() }
+  }
+
+  // The message along with the line of the script that the token is on.
+  pub fn render(&self, program: &Program) -> String {
+    let severity = match self.severity {
+      Severity::Error => "Error",
+      Severity::Warning => "Warning",
+    };
+    let end = self.token.index + self.token.to_string().chars().count();
+    program.get_error_msg(&format!("{}: {}", severity, self.message), self.token.index, end)
+  }
+}
+
+// Runs every check, errors first and then in the order of the script.
+pub fn check(game: &Game) -> Vec<Diagnostic> {
+  let mut diagnostics = Vec::new();
+  diagnostics.extend(check_gotos(game));
+  diagnostics.extend(check_reachable(game));
+  diagnostics.extend(check_items(game));
+  diagnostics.extend(check_sounds(game));
+  diagnostics.extend(check_interactables(game));
+  diagnostics.extend(check_shadowed(game));
+  diagnostics.sort_by_key(|d| (d.severity, d.token.index));
+  diagnostics
+}
+
+pub fn scopes<'a>(rooms: &'a [GameRoom], actions: &'a [GameAction]) -> impl Iterator<Item = &'a Vec<ParseNode>> {
+  rooms.iter().map(|r| &r.scope).chain(actions.iter().map(|a| &a.scope))
+}
+
+pub fn gotos(scope: &[ParseNode]) -> impl Iterator<Item = &Token> {
+  scope.iter().filter_map(|node| match &node.value {
+    Expr::Goto(token) => Some(token),
+    _ => None,
+  })
+}
+
+// Every goto must lead to a room that exists.
+pub fn check_gotos(game: &Game) -> Vec<Diagnostic> {
+  let names: Vec<&String> = game.rooms.iter().filter(|(_n, (r, _a))| !r.is_empty()).map(|(n, _)| n).collect();
+  let mut diagnostics = Vec::new();
+  for (rooms, actions) in game.rooms.values() {
+    for token in scopes(rooms, actions).flat_map(|scope| gotos(scope)) {
+      let target = token.to_string();
+      if names.contains(&&target) {
+        continue;
+      }
+      let message = match closest(&target, &names) {
+        Some(name) => format!("Goto to the unknown ROOM |{}|, did you mean '{}'?", target, name),
+        None => format!("Goto to the unknown ROOM |{}|", target),
+      };
+      diagnostics.push(Diagnostic::error(message, token));
+    }
+  }
+  diagnostics
+}
+
+// The rooms that gotos lead to from the start of the game, whatever the requirements of the sections.
+pub fn reachable_rooms(game: &Game) -> BTreeSet<String> {
+  let mut reached = BTreeSet::new();
+  let mut queue = VecDeque::new();
+  queue.push_back(START_ROOM.to_string());
+  while let Some(name) = queue.pop_front() {
+    let (rooms, actions) = match game.rooms.get(&name) {
+      Some(room) => room,
+      None => continue,
+    };
+    if !reached.insert(name) {
+      continue;
+    }
+    for token in scopes(rooms, actions).flat_map(|scope| gotos(scope)) {
+      queue.push_back(token.to_string());
+    }
+  }
+  reached
+}
+
+// Every room should be reachable by gotos from the start of the game.
+pub fn check_reachable(game: &Game) -> Vec<Diagnostic> {
+  let reached = reachable_rooms(game);
+  game.rooms.iter()
+    .filter(|(name, (rooms, _a))| !rooms.is_empty() && !reached.contains(*name))
+    .map(|(name, (rooms, _a))| Diagnostic::warning(format!("ROOM |{}| is never reached from ROOM |{}|", name, START_ROOM), &rooms[0].name))
+    .collect()
+}
+
+fn sounds(scope: &[ParseNode]) -> impl Iterator<Item = &GameAudio> {
+  scope.iter().filter_map(|node| match &node.value {
+    Expr::Audio(game_audio) => Some(game_audio),
+    _ => None,
+  })
+}
+
+const SOUND_EXTENSIONS: [&str; 5] = ["mp3", "wav", "ogg", "m4a", "flac"];
+
+// The sound files under the root, relative to it and with '/' between directories like the
+// paths in the script.
+fn sound_files(root: &Path) -> Vec<String> {
+  let mut files = Vec::new();
+  let mut directories = vec![root.to_path_buf()];
+  while let Some(directory) = directories.pop() {
+    let entries = match std::fs::read_dir(&directory) {
+      Ok(entries) => entries,
+      Err(_msg) => continue,
+    };
+    for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+      if path.is_dir() {
+        // Installed packages of the web build are not assets of the game.
+        if !path.ends_with("node_modules") {
+          directories.push(path);
+        }
+        continue;
+      }
+      let is_sound = path.extension().and_then(|ext| ext.to_str()).is_some_and(|ext| SOUND_EXTENSIONS.contains(&ext.to_lowercase().as_str()));
+      if let (true, Ok(relative)) = (is_sound, path.strip_prefix(root)) {
+        let parts: Vec<String> = relative.components().map(|c| c.as_os_str().to_string_lossy().to_string()).collect();
+        files.push(parts.join("/"));
+      }
+    }
+  }
+  files.sort();
+  files
+}
+
+// Every sound in the SOUNDS sections should be played somewhere.
+pub fn check_sounds(game: &Game) -> Vec<Diagnostic> {
+  let played: BTreeSet<String> = game.rooms.values()
+    .flat_map(|(rooms, actions)| scopes(rooms, actions).flat_map(|scope| sounds(scope)).collect::<Vec<&GameAudio>>())
+    .filter_map(|game_audio| game_audio.sound.as_ref().map(|id| id.to_string()))
+    .collect();
+  game.get_sounds().iter()
+    .filter(|game_sound| !played.contains(&game_sound.id.to_string()))
+    .map(|game_sound| Diagnostic::warning(format!("The sound '{}' is never played", game_sound.id.to_string()), &game_sound.id))
+    .collect()
+}
+
+// The highlighted '#..#' text of a scope.
+fn interactables(scope: &[ParseNode]) -> Vec<&Token> {
+  let mut tokens = Vec::new();
+  for node in scope.iter() {
+    if let Expr::Text(game_text) = &node.value {
+      let mut highlighted = false;
+      for token in game_text.text.iter() {
+        match token.kind {
+          TokenKind::Pound => highlighted = !highlighted,
+          _ if highlighted => tokens.push(token),
+          _ => (),
+        }
+      }
+    }
+  }
+  tokens
+}
+
+// The nouns highlighted in the sections of a room, each with the first token that highlights it.
+// Commands like '#go west#' or '#help#' in the instructions are left out.
+pub fn highlighted_nouns<'a>(rooms: &'a [GameRoom], actions: &'a [GameAction]) -> Vec<(String, &'a Token)> {
+  let mut nouns: Vec<(String, &Token)> = Vec::new();
+  for token in scopes(rooms, actions).flat_map(|scope| interactables(scope)) {
+    let text = token.to_string();
+    let noun = interactable_noun(&text);
+    let is_command = text.split_whitespace().count() > 1 || is_keyword(&text.to_uppercase()) || noun == "inventory";
+    if !is_command && !nouns.iter().any(|(n, _t)| *n == noun) {
+      nouns.push((noun, token));
+    }
+  }
+  nouns
+}
+
+// The lowercase names of the actions of a room that are one of the kinds, ie 'EXAMINE'.
+pub fn action_names(actions: &[GameAction], kinds: &[&str]) -> BTreeSet<String> {
+  actions.iter()
+    .filter(|a| kinds.contains(&a.action.to_string().as_str()))
+    .map(|a| a.name.to_string().to_lowercase())
+    .collect()
+}
+
+// Every noun highlighted in a room should have an EXAMINE section in that room. Directions and
+// answers, which have a GO or MISC section instead, are left out.
+pub fn check_interactables(game: &Game) -> Vec<Diagnostic> {
+  let mut diagnostics = Vec::new();
+  for (name, (rooms, actions)) in game.rooms.iter() {
+    let examined = action_names(actions, &["EXAMINE"]);
+    let others = action_names(actions, &["GO", "MISC", "HELP"]);
+    for (noun, token) in highlighted_nouns(rooms, actions) {
+      if examined.contains(&noun) || others.contains(&noun) {
+        continue;
+      }
+      let message = format!("The highlighted '#{}#' has no EXAMINE section in ROOM |{}|", token.to_string(), name);
+      diagnostics.push(Diagnostic::warning(message, token));
+    }
+  }
+  diagnostics
+}
+
+// Every sound the script plays or declares must be a file under the asset root. A sound played by
+// its id is reported once, at its entry in the SOUNDS section.
+pub fn check_assets(game: &Game, root: &Path) -> Vec<Diagnostic> {
+  let files = sound_files(root);
+  let names: Vec<&String> = files.iter().collect();
+  let mut paths: Vec<&Token> = game.get_sounds().iter().map(|game_sound| &game_sound.path).collect();
+  for (rooms, actions) in game.rooms.values() {
+    paths.extend(scopes(rooms, actions).flat_map(|scope| sounds(scope)).map(|game_audio| &game_audio.path));
+  }
+  paths.sort_by_key(|token| token.index);
+  paths.dedup_by_key(|token| token.index);
+
+  let mut diagnostics = Vec::new();
+  for token in paths {
+    let path = token.to_string();
+    if root.join(&path).is_file() {
+      continue;
+    }
+    let message = match closest(&path, &names) {
+      Some(file) => format!("The sound '{}' does not exist in {}, did you mean '{}'?", path, root.display(), file),
+      None => format!("The sound '{}' does not exist in {}", path, root.display()),
+    };
+    diagnostics.push(Diagnostic::error(message, token));
+  }
+  diagnostics
+}
+
+// The sound files under the asset root that the script never plays.
+pub fn unreferenced_assets(game: &Game, root: &Path) -> Vec<String> {
+  let played: BTreeSet<String> = game.rooms.values()
+    .flat_map(|(rooms, actions)| scopes(rooms, actions).flat_map(|scope| sounds(scope)).collect::<Vec<&GameAudio>>())
+    .map(|game_audio| game_audio.path.to_string())
+    .collect();
+  sound_files(root).into_iter().filter(|file| !played.contains(file)).collect()
+}
+
+// A field of the cue sheet in double quotes, with the quotes in it doubled as RFC 4180 asks.
+pub fn csv_field(text: &str) -> String {
+  format!("\"{}\"", text.replace('"', "\"\""))
+}
+
+// Every sound the script plays as CSV, in the order of the script, with the section that plays it,
+// the id it was played by and its caption, if any.
+pub fn cue_sheet(game: &Game, program: &Program) -> String {
+  let mut cues = Vec::new();
+  for (room_name, (rooms, actions)) in game.rooms.iter() {
+    let sections = rooms.iter().map(|r| (format!("ROOM [{}]", room_name), &r.scope))
+      .chain(actions.iter().map(|a| (format!("{} [{}]", a.action.to_string(), a.name.to_string()), &a.scope)));
+    for (section, scope) in sections {
+      for game_audio in sounds(scope) {
+        let (row, _col) = program.get_location_with_token(game_audio.sound.as_ref().unwrap_or(&game_audio.path));
+        let kind = if game_audio.sound_effect { "sound_effect" } else { "music" };
+        let id = game_audio.sound.as_ref().map(|id| id.to_string()).unwrap_or_default();
+        let caption = game_audio.caption.clone().unwrap_or_default();
+        cues.push((row + 1, room_name.to_string(), section.clone(), kind, game_audio.path.to_string(), id, caption));
+      }
+    }
+  }
+  cues.sort();
+
+  let mut sheet = String::from("line,room,section,kind,path,sound,caption\n");
+  for (line, room, section, kind, path, id, caption) in cues {
+    let fields: Vec<String> = [room.as_str(), section.as_str(), kind, path.as_str(), id.as_str(), caption.as_str()].iter().map(|field| csv_field(field)).collect();
+    sheet.push_str(&format!("{},{}\n", line, fields.join(",")));
+  }
+  sheet
+}
+
+// How an item is written in the script, with the room its inventory belongs to if any.
+fn describe_item(item: &GameItem, room_name: &str) -> String {
+  match item.inventory {
+    InventoryKind::Personal => format!("${}$", item.name.to_string()),
+    InventoryKind::Room => format!("&{}& in ROOM |{}|", item.name.to_string(), room_name),
+    InventoryKind::Global => format!("%{}%", item.name.to_string()),
+  }
+}
+
+// Requirements the way they are written in a section, ie '&your_room& %good%'.
+pub fn describe_requirements(requirements: &[GameItem]) -> String {
+  requirements.iter().map(|item| match item.inventory {
+    InventoryKind::Personal => format!("${}$", item.name.to_string()),
+    InventoryKind::Room => format!("&{}&", item.name.to_string()),
+    InventoryKind::Global => format!("%{}%", item.name.to_string()),
+  }).collect::<Vec<String>>().join(" ")
+}
+
+// A section that is looked up together with others of its group, see SectionGroup.
+pub struct Section<'a> {
+  pub requirements: &'a Vec<GameItem>,
+  pub priority: u32,
+  pub token: &'a Token,
+}
+
+impl<'a> Section<'a> {
+  pub fn rank(&self) -> (u32, usize) {
+    section_rank(self.requirements, self.priority)
+  }
+}
+
+// Sections that are looked up together, where the one that runs is the best ranked of those whose
+// requirements pass (see choose_section). These are the variants of a room and the actions of a
+// room with the same verb and noun.
+pub struct SectionGroup<'a> {
+  pub room: &'a String,
+  // How the sections are written, ie 'ROOM |kitchen|' or 'EXAMINE |glass|'.
+  pub name: String,
+  // In the order of the script.
+  pub sections: Vec<Section<'a>>,
+}
+
+impl<'a> SectionGroup<'a> {
+  // How a section of the group is told apart from the others.
+  pub fn describe(&self, section: usize) -> String {
+    let section = &self.sections[section];
+    let requirements = match section.requirements.as_slice() {
+      [] => format!("{} with no requirements", self.name),
+      requirements => format!("{} with {}", self.name, describe_requirements(requirements)),
+    };
+    match section.priority {
+      0 => requirements,
+      priority => format!("{} |PRIORITY {}|", requirements, priority),
+    }
+  }
+}
+
+// Every group with more than one section, in the order of the script.
+pub fn section_groups(game: &Game) -> Vec<SectionGroup<'_>> {
+  let mut groups = Vec::new();
+  for (room_name, (rooms, actions)) in game.rooms.iter() {
+    if rooms.len() > 1 {
+      groups.push(SectionGroup {
+        room: room_name,
+        name: format!("ROOM |{}|", room_name),
+        sections: rooms.iter().map(|r| Section { requirements: &r.requirements, priority: r.priority, token: &r.name }).collect(),
+      });
+    }
+    let mut targets: BTreeMap<(String, String), Vec<&GameAction>> = BTreeMap::new();
+    for action in actions.iter() {
+      targets.entry((action.action.to_string().to_uppercase(), action.name.to_string().to_lowercase())).or_default().push(action);
+    }
+    for ((verb, noun), actions) in targets.into_iter().filter(|(_k, actions)| actions.len() > 1) {
+      groups.push(SectionGroup {
+        room: room_name,
+        name: format!("{} |{}|", verb, noun),
+        sections: actions.iter().map(|a| Section { requirements: &a.requirements, priority: a.priority, token: &a.name }).collect(),
+      });
+    }
+  }
+  groups.sort_by_key(|group| group.sections[0].token.index);
+  groups
+}
+
+// Whether every requirement of the first section is also one of the second's, so that the first
+// passes whenever the second does.
+pub fn is_subset(first: &[GameItem], second: &[GameItem]) -> bool {
+  first.iter().all(|a| second.iter().any(|b| inventory_kind_to_string(&a.inventory) == inventory_kind_to_string(&b.inventory) && a.name.to_string() == b.name.to_string()))
+}
+
+// A section never runs when another of its group passes whenever it does and is chosen over it,
+// ie the same requirements earlier in the script or fewer of them with a higher priority. Sections
+// that can both pass and are only told apart by their order are found by the solver, see
+// Exploration::ambiguous_sections.
+pub fn check_shadowed(game: &Game) -> Vec<Diagnostic> {
+  let mut diagnostics = Vec::new();
+  for group in section_groups(game).iter() {
+    for (i, section) in group.sections.iter().enumerate() {
+      let winner = (0..group.sections.len()).find(|j| {
+        let other = &group.sections[*j];
+        *j != i && is_subset(other.requirements, section.requirements) && (other.rank() > section.rank() || (other.rank() == section.rank() && *j < i))
+      });
+      if let Some(winner) = winner {
+        let message = format!("{} never runs, {} passes whenever it does and is chosen over it. Give it requirements the other does not have or a higher |PRIORITY n|",
+          group.describe(i), group.describe(winner));
+        diagnostics.push(Diagnostic::warning(message, section.token));
+      }
+    }
+  }
+  diagnostics
+}
+
+// Remembers the first place in the script that the item is found.
+fn note_item<'a>(items: &mut BTreeMap<String, &'a Token>, key: String, token: &'a Token) {
+  let first = items.entry(key).or_insert(token);
+  if token.index < first.index {
+    *first = token;
+  }
+}
+
+// Every item that is required should be added somewhere, every item that is added should be
+// required somewhere and every item that is removed should be added somewhere. A room item
+// belongs to the room whose section requires or modifies it, as that is the room the player is in.
+pub fn check_items(game: &Game) -> Vec<Diagnostic> {
+  let mut required: BTreeMap<String, &Token> = BTreeMap::new();
+  let mut added: BTreeMap<String, &Token> = BTreeMap::new();
+  let mut removed: BTreeMap<String, &Token> = BTreeMap::new();
+  for (room_name, (rooms, actions)) in game.rooms.iter() {
+    let requirements = rooms.iter().flat_map(|r| r.requirements.iter()).chain(actions.iter().flat_map(|a| a.requirements.iter()));
+    for item in requirements {
+      note_item(&mut required, describe_item(item, room_name), &item.name);
+    }
+    for node in scopes(rooms, actions).flat_map(|scope| scope.iter()) {
+      if let Expr::Modify(item) = &node.value {
+        match item.action {
+          InventoryAction::Add => note_item(&mut added, describe_item(item, room_name), &item.name),
+          InventoryAction::Remove => note_item(&mut removed, describe_item(item, room_name), &item.name),
+          InventoryAction::Check => (),
+        }
+      }
+    }
+  }
+
+  let mut diagnostics = Vec::new();
+  for (item, token) in required.iter().filter(|(item, _t)| !added.contains_key(*item)) {
+    diagnostics.push(Diagnostic::warning(format!("{} is required but never added, so this section can never be used", item), token));
+  }
+  for (item, token) in added.iter().filter(|(item, _t)| !required.contains_key(*item)) {
+    diagnostics.push(Diagnostic::warning(format!("{} is added but never required by any section", item), token));
+  }
+  for (item, token) in removed.iter().filter(|(item, _t)| !added.contains_key(*item)) {
+    diagnostics.push(Diagnostic::warning(format!("{} is removed but never added", item), token));
+  }
+  diagnostics
+}
+
+// The known name that is the fewest edits away, if it is close enough to be a typo.
+pub fn closest<'a>(name: &str, names: &[&'a String]) -> Option<&'a String> {
+  let limit = std::cmp::max(2, name.chars().count() / 3);
+  names.iter()
+    .map(|n| (levenshtein(name, n), *n))
+    .filter(|(distance, _n)| *distance <= limit)
+    .min()
+    .map(|(_d, n)| n)
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+  let b: Vec<char> = b.chars().collect();
+  let mut row: Vec<usize> = (0..=b.len()).collect();
+  for (i, ca) in a.chars().enumerate() {
+    let mut previous = row[0];
+    row[0] = i + 1;
+    for (j, cb) in b.iter().enumerate() {
+      let substitution = if ca == *cb { previous } else { previous + 1 };
+      previous = row[j + 1];
+      row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+    }
+  }
+  row[b.len()]
+}
+
+// The number of errors, a script with any of them should not be shipped.
+pub fn count_errors(diagnostics: &[Diagnostic]) -> usize {
+  diagnostics.iter().filter(|d| d.severity == Severity::Error).count()
+}
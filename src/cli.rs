@@ -0,0 +1,372 @@
+// The command line interface of the terminal binary.
+//
+//   reflections [COMMAND] [OPTIONS] [ARGS]
+//
+//   play                     Play the story in the terminal, the default command
+//   check                    Report mistakes in the script, see check.rs
+//   solve                    Explore every state of the story for ways to get stuck, see solver.rs
+//   walkthrough [GOAL]       Print the shortest walkthroughs to every ending, a room or an item
+//   cues                     Print which section plays which sound as CSV
+//   coverage                 Print which verbs have a section for the nouns of each room, see coverage.rs
+//   dot                      Print the rooms and the gotos between them as a Graphviz graph, see dot.rs
+//   test [PLAYTEST..]        Run the TEST sections of the script and the given playthroughs
+//   playtest PLAYTEST        Run a single playthrough, see playtest.rs
+//   replay TRANSCRIPT        Replay a transcript and compare it with what was recorded
+//   tokens                   Print the tokens of the script
+//   ast                      Print the parsed script as JSON
+//
+//   --script FILE            The script to use, www/narrative.txt by default
+//   --assets DIR             Where the sounds of the script are, the directory of the script by default
+//   --captions               Show sounds as captions when playing
+//   --record FILE            Record a transcript while playing
+//   --requirements           Label the gotos of the graph with the requirements of their sections
+//
+// The commands can also be given as flags, ie '--check', as they were before there were commands.
+// The exit code is 0 on success, 1 when the script has mistakes or a test fails and 2 when the
+// arguments are wrong or a file cannot be read.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use super::{Program, build_game, check, coverage, dot, json, lex, parse, playtest, read_program, solver, start_game, transcript};
+
+const SUCCESS: i32 = 0;
+const FAILURE: i32 = 1;
+const USAGE: i32 = 2;
+
+const COMMANDS: [&str; 12] = ["play", "check", "solve", "walkthrough", "cues", "coverage", "dot", "test", "playtest", "replay", "tokens", "ast"];
+
+const USAGE_TEXT: &str = "Usage: reflections [COMMAND] [OPTIONS] [ARGS]
+
+Commands:
+  play                  Play the story in the terminal (default)
+  check                 Report mistakes in the script
+  solve                 Explore every state of the story for ways to get stuck
+  walkthrough [GOAL]    Print the shortest walkthroughs to every ending, a room or an item
+  cues                  Print which section plays which sound as CSV
+  coverage              Print which verbs have a section for the nouns of each room
+  dot                   Print the rooms and the gotos between them as a Graphviz graph
+  test [PLAYTEST..]     Run the TEST sections of the script and the given playthroughs
+  playtest PLAYTEST     Run a single playthrough
+  replay TRANSCRIPT     Replay a transcript and compare it with what was recorded
+  tokens                Print the tokens of the script
+  ast                   Print the parsed script as JSON
+
+Options:
+  --script FILE         The script to use, www/narrative.txt by default
+  --assets DIR          Where the sounds of the script are, the directory of the script by default
+  --captions            Show sounds as captions when playing
+  --record FILE         Record a transcript while playing
+  --requirements        Label the gotos of the graph with the requirements of their sections
+  --help                Print this message";
+
+struct Options {
+  command: String,
+  args: Vec<String>,
+  script: PathBuf,
+  assets: Option<PathBuf>,
+  captions: bool,
+  record: Option<String>,
+  requirements: bool,
+  help: bool,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+  let mut options = Options {
+    command: String::new(),
+    args: Vec::new(),
+    script: PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("www").join("narrative.txt"),
+    assets: None,
+    captions: false,
+    record: None,
+    requirements: false,
+    help: false,
+  };
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    let mut value = |name: &str| match args.next() {
+      Some(value) => Ok(value.to_string()),
+      None => Err(format!("Expected a value after '{}'", name)),
+    };
+    match arg.as_str() {
+      "--script" => options.script = PathBuf::from(value(arg)?),
+      "--assets" => options.assets = Some(PathBuf::from(value(arg)?)),
+      "--record" => options.record = Some(value(arg)?),
+      "--captions" => options.captions = true,
+      "--requirements" => options.requirements = true,
+      "--help" | "-h" => options.help = true,
+      flag if options.command.is_empty() && flag.strip_prefix("--").is_some_and(|c| COMMANDS.contains(&c)) => {
+        options.command = flag[2..].to_string();
+      },
+      flag if flag.starts_with('-') => return Err(format!("Unknown option '{}'", flag)),
+      command if options.command.is_empty() => {
+        if !COMMANDS.contains(&command) {
+          return Err(format!("Unknown command '{}'", command));
+        }
+        options.command = command.to_string();
+      },
+      operand => options.args.push(operand.to_string()),
+    }
+  }
+  if options.command.is_empty() {
+    options.command = "play".to_string();
+  }
+  Ok(options)
+}
+
+// Runs the command line and returns the exit code.
+pub fn main(args: &[String]) -> i32 {
+  let options = match parse_args(args) {
+    Ok(options) => options,
+    Err(msg) => {
+      eprintln!("{}\n\n{}", msg, USAGE_TEXT);
+      return USAGE;
+    },
+  };
+  if options.help {
+    println!("{}", USAGE_TEXT);
+    return SUCCESS;
+  }
+  let mut program = match read_program(&options.script) {
+    Ok(program) => program,
+    Err(msg) => {
+      eprintln!("{}", msg);
+      return USAGE;
+    },
+  };
+
+  // The tokens and the AST are wanted for scripts that do not compile, so they come before it.
+  match options.command.as_str() {
+    "tokens" => return print_tokens(&mut program),
+    "ast" => return print_ast(&mut program),
+    _ => (),
+  }
+
+  let mut game = match build_game(&mut program) {
+    Ok(game) => game,
+    Err(msg) => {
+      eprintln!("Error: {}", msg);
+      return FAILURE;
+    },
+  };
+  // The solver reports a panic as a failure, so the default hook printing it would only add noise.
+  if ["solve", "walkthrough", "coverage"].contains(&options.command.as_str()) {
+    std::panic::set_hook(Box::new(|_info| ()));
+  }
+  let assets = match &options.assets {
+    Some(assets) => assets.clone(),
+    None => options.script.parent().map(Path::to_path_buf).unwrap_or_default(),
+  };
+
+  match (options.command.as_str(), options.args.first()) {
+    ("play", _) => {
+      game.set_captions(options.captions);
+      let recorder = match &options.record {
+        Some(path) => match transcript::Recorder::create(&game, path) {
+          Ok(recorder) => Some(recorder),
+          Err(msg) => {
+            eprintln!("{}", msg);
+            return USAGE;
+          },
+        },
+        None => None,
+      };
+      match start_game(&game, recorder) {
+        Ok(_) => SUCCESS,
+        Err(msg) => {
+          eprintln!("Error: {}", msg);
+          FAILURE
+        },
+      }
+    },
+    ("check", _) => {
+      let mut diagnostics = check::check(&game);
+      diagnostics.extend(check::check_assets(&game, &assets));
+      diagnostics.sort_by_key(|d| (d.severity, d.token.index));
+      for diagnostic in diagnostics.iter() {
+        println!("{}\n", diagnostic.render(&program));
+      }
+      let unused = check::unreferenced_assets(&game, &assets);
+      for path in unused.iter() {
+        println!("Warning: The sound '{}' is never played\n", path);
+      }
+      let errors = check::count_errors(&diagnostics);
+      println!("{} errors, {} warnings", errors, diagnostics.len() - errors + unused.len());
+      if errors > 0 { FAILURE } else { SUCCESS }
+    },
+    ("solve", _) => {
+      let exploration = solver::explore(&game, &solver::SolverLimits::default());
+      println!("Explored {} states, {} of them endings and {} finishing the story", exploration.states.len(), exploration.endings.len(), exploration.finished.len());
+      if exploration.truncated {
+        println!("Warning: the limits were reached before every state was explored\n");
+      }
+      for group in exploration.failure_groups().iter() {
+        let mut commands = exploration.path_to(group.first.state);
+        commands.push(group.first.command.clone());
+        let others = match group.count {
+          1 => String::new(),
+          count => format!(" and in {} other states of ROOM |{}|", count - 1, group.room),
+        };
+        println!("Failure after '{}'{}\n  {}\n", commands.join(", "), others, group.first.message);
+      }
+      let stuck = exploration.points_of_no_return();
+      for state in stuck.iter() {
+        match exploration.path_to(*state).as_slice() {
+          [] => println!("The story cannot be finished from the start of the game\n"),
+          commands => println!("The story cannot be finished after '{}'\n", commands.join(", ")),
+        }
+      }
+      for diagnostic in exploration.ambiguous_sections(&game).iter() {
+        println!("{}\n", diagnostic.render(&program));
+      }
+      for diagnostic in exploration.unreachable_sections(&game).iter() {
+        println!("{}\n", diagnostic.render(&program));
+      }
+      if exploration.failures.is_empty() && stuck.is_empty() { SUCCESS } else { FAILURE }
+    },
+    ("walkthrough", goal) => {
+      let goal = match goal {
+        Some(goal) => solver::Goal::parse(goal),
+        None => solver::Goal::Endings,
+      };
+      let exploration = solver::explore(&game, &solver::SolverLimits::default());
+      let walkthroughs = solver::walkthroughs(&exploration, &goal);
+      if walkthroughs.is_empty() {
+        println!("No walkthrough could be found after exploring {} states", exploration.states.len());
+        return FAILURE;
+      }
+      for walkthrough in walkthroughs.iter() {
+        println!("// Walkthrough to ROOM |{}| in {} commands", walkthrough.state.get_room_name(), walkthrough.commands.len());
+        println!("{}", walkthrough.to_playtest());
+      }
+      SUCCESS
+    },
+    ("cues", _) => {
+      write_out(&check::cue_sheet(&game, &program));
+      SUCCESS
+    },
+    ("coverage", _) => {
+      let exploration = solver::explore(&game, &solver::SolverLimits::default());
+      if exploration.truncated {
+        println!("Warning: the limits were reached before every state was explored\n");
+      }
+      write_out(&coverage::render(&coverage::verb_coverage(&game, &exploration), &exploration));
+      SUCCESS
+    },
+    ("dot", _) => {
+      write_out(&dot::to_dot(&game, options.requirements));
+      SUCCESS
+    },
+    ("test", _) => {
+      let mut failed = 0;
+      for game_test in game.get_tests().iter() {
+        match playtest::run_test(&game, game_test).failure {
+          Some(failure) => {
+            println!("TEST |{}| failed\n{}\n", game_test.name.to_string(), failure);
+            failed += 1;
+          },
+          None => println!("TEST |{}| passed", game_test.name.to_string()),
+        }
+      }
+      for path in options.args.iter() {
+        match playtest::run_file(&game, path) {
+          Ok(playtest) => match playtest.failure {
+            Some(failure) => {
+              println!("{} failed\n{}\n", path, failure);
+              failed += 1;
+            },
+            None => println!("{} passed", path),
+          },
+          Err(msg) => {
+            eprintln!("{}", msg);
+            return USAGE;
+          },
+        }
+      }
+      let total = game.get_tests().len() + options.args.len();
+      println!("{} of {} tests passed", total - failed, total);
+      if failed > 0 { FAILURE } else { SUCCESS }
+    },
+    ("playtest", Some(path)) => {
+      let playtest = match playtest::run_file(&game, path) {
+        Ok(playtest) => playtest,
+        Err(msg) => {
+          eprintln!("{}", msg);
+          return USAGE;
+        },
+      };
+      match playtest.failure {
+        Some(failure) => {
+          println!("{}", failure);
+          FAILURE
+        },
+        None => {
+          println!("All {} turns of {} passed", playtest.steps, path);
+          SUCCESS
+        },
+      }
+    },
+    ("replay", Some(path)) => {
+      let replay = match transcript::replay_file(&game, path) {
+        Ok(replay) => replay,
+        Err(msg) => {
+          eprintln!("{}", msg);
+          return USAGE;
+        },
+      };
+      if !replay.fingerprint_matches {
+        println!("Warning: the transcript was recorded against a different version of the story");
+      }
+      match replay.difference {
+        Some(difference) => {
+          println!("{}", difference);
+          FAILURE
+        },
+        None => {
+          println!("All {} turns of {} matched", replay.turns, path);
+          SUCCESS
+        },
+      }
+    },
+    (command, _) => {
+      eprintln!("Expected a file after '{}'\n\n{}", command, USAGE_TEXT);
+      USAGE
+    },
+  }
+}
+
+// Writes to stdout without the panic of print! when the reader has gone away, ie '| head'.
+fn write_out(text: &str) {
+  let _ = std::io::stdout().write_all(text.as_bytes());
+}
+
+fn print_tokens(program: &mut Program) -> i32 {
+  match lex(program) {
+    Ok(tokens) => {
+      program.tokens = tokens;
+      program.print_tokens(&mut std::io::stdout());
+      write_out("\n");
+      SUCCESS
+    },
+    Err(msg) => {
+      eprintln!("Error: {}", msg);
+      FAILURE
+    },
+  }
+}
+
+fn print_ast(program: &mut Program) -> i32 {
+  let nodes = lex(program).and_then(|tokens| {
+    program.tokens = tokens;
+    parse(program)
+  });
+  match nodes {
+    Ok(nodes) => {
+      write_out(&format!("{}\n", json::to_json(&nodes)));
+      SUCCESS
+    },
+    Err(msg) => {
+      eprintln!("Error: {}", msg);
+      FAILURE
+    },
+  }
+}
@@ -0,0 +1,139 @@
+// A report of which verbs a player can use on the nouns of each room, for QA to find the nouns that
+// answer 'take bed' with an error.
+//
+// The nouns of a room are the ones highlighted in its text and the names of its sections that its
+// text mentions. For every noun and verb the report lists the sections found for them in the states
+// the solver reached in the room, named by their requirements, ie 'EXAMINE in %good% and %bad%', and
+// the shortest way to a state where none is found. Directions and answers, the nouns with a GO, MISC
+// or HELP section, are only reported for the verbs that have a section for them.
+
+use std::collections::BTreeSet;
+use super::{Expr, Game, GameItem, text_to_string};
+use super::check::{action_names, describe_requirements, highlighted_nouns, scopes};
+use super::solver::Exploration;
+
+// The verbs every noun is reported for, any other verb only when the room has a section for it.
+const VERBS: [&str; 4] = ["EXAMINE", "USE", "TAKE", "TALK"];
+
+pub struct VerbCoverage {
+  pub verb: String,
+  // The requirements of every section found, in the order of the script.
+  pub sections: Vec<String>,
+  // A reachable state where no section is found, if there is one.
+  pub missing: Option<usize>,
+}
+
+pub struct NounCoverage {
+  pub noun: String,
+  pub verbs: Vec<VerbCoverage>,
+}
+
+pub struct RoomCoverage {
+  pub room: String,
+  // The number of reachable states in the room.
+  pub states: usize,
+  pub nouns: Vec<NounCoverage>,
+}
+
+// The requirements of a section the way they are written in the script, ie '&your_room& %good%'.
+fn describe_section(requirements: &[GameItem]) -> String {
+  match requirements {
+    [] => "any state".to_string(),
+    requirements => describe_requirements(requirements),
+  }
+}
+
+// The words of a room's text, to find which of its sections it mentions.
+fn mentioned_words(game: &Game, room: &str) -> BTreeSet<String> {
+  let (rooms, actions) = &game.rooms[room];
+  scopes(rooms, actions)
+    .flat_map(|scope| scope.iter())
+    .filter_map(|node| match &node.value {
+      Expr::Text(game_text) => Some(text_to_string(game_text).to_lowercase()),
+      _ => None,
+    })
+    .flat_map(|text| text.split(|ch: char| !ch.is_alphanumeric() && ch != '_').map(String::from).collect::<Vec<String>>())
+    .collect()
+}
+
+pub fn verb_coverage(game: &Game, exploration: &Exploration) -> Vec<RoomCoverage> {
+  let mut room_names: Vec<&String> = game.rooms.keys().filter(|name| !game.rooms[*name].0.is_empty()).collect();
+  room_names.sort();
+
+  let mut report = Vec::new();
+  for room in room_names {
+    let (rooms, actions) = &game.rooms[room];
+    let states: Vec<usize> = (0..exploration.states.len()).filter(|i| exploration.states[*i].get_room_name() == room).collect();
+
+    let mut nouns: Vec<String> = highlighted_nouns(rooms, actions).into_iter().map(|(noun, _token)| noun).collect();
+    let words = mentioned_words(game, room);
+    for action in actions.iter() {
+      let name = action.name.to_string().to_lowercase();
+      if words.contains(&name) && !nouns.contains(&name) {
+        nouns.push(name);
+      }
+    }
+
+    let answers = action_names(actions, &["GO", "MISC", "HELP"]);
+    let mut coverage = Vec::new();
+    for noun in nouns {
+      let mut verbs: Vec<String> = match answers.contains(&noun) {
+        true => Vec::new(),
+        false => VERBS.iter().map(|verb| verb.to_string()).collect(),
+      };
+      for action in actions.iter() {
+        let verb = action.action.to_string();
+        if action.name.to_string().to_lowercase() == noun && !verbs.contains(&verb) {
+          verbs.push(verb);
+        }
+      }
+
+      let verbs = verbs.into_iter().map(|verb| {
+        let mut found = BTreeSet::new();
+        let mut missing = None;
+        for state in states.iter() {
+          match game.find_action_index(&verb, &noun, &exploration.states[*state]) {
+            Ok(index) => { found.insert(index); },
+            Err(_msg) => missing = missing.or(Some(*state)),
+          }
+        }
+        let sections = found.iter().map(|index| describe_section(&actions[*index].requirements)).collect();
+        VerbCoverage { verb, sections, missing }
+      }).collect();
+      coverage.push(NounCoverage { noun, verbs });
+    }
+    report.push(RoomCoverage { room: room.to_string(), states: states.len(), nouns: coverage });
+  }
+  report
+}
+
+// The report as text, one line per noun, ie
+//   bed: EXAMINE in &your_room& %good% and &your_room& %bad%, TAKE missing
+pub fn render(report: &[RoomCoverage], exploration: &Exploration) -> String {
+  let mut text = String::new();
+  for room in report.iter() {
+    if room.nouns.is_empty() {
+      continue;
+    }
+    if room.states == 0 {
+      text.push_str(&format!("ROOM |{}| is never reached\n\n", room.room));
+      continue;
+    }
+    text.push_str(&format!("ROOM |{}| in {} states\n", room.room, room.states));
+    for noun in room.nouns.iter() {
+      let verbs: Vec<String> = noun.verbs.iter().map(|coverage| {
+        match (coverage.sections.as_slice(), coverage.missing) {
+          ([], _) => format!("{} missing", coverage.verb),
+          (sections, None) => format!("{} in {}", coverage.verb, sections.join(" and ")),
+          (sections, Some(state)) => match exploration.path_to(state).as_slice() {
+            [] => format!("{} in {} but missing at the start", coverage.verb, sections.join(" and ")),
+            commands => format!("{} in {} but missing after '{}'", coverage.verb, sections.join(" and "), commands.join(", ")),
+          },
+        }
+      }).collect();
+      text.push_str(&format!("  {}: {}\n", noun.noun, verbs.join(", ")));
+    }
+    text.push('\n');
+  }
+  text
+}
@@ -0,0 +1,76 @@
+// The rooms of the story and the gotos between them as a Graphviz graph, for reviewing the pacing
+// of the story and finding branches that cannot be reached.
+//
+//   reflections dot | dot -Tsvg > story.svg
+//
+// Every room is a node and every goto an edge from the room of its section, labeled with the
+// section, ie 'GO [east]' or 'ROOM [hall]' for a goto in the text of a room. With requirements the
+// labels also list the requirements of the section, ie 'USE [door] %key%'. The start room is bold,
+// rooms that are never reached are dashed and gotos to rooms that do not exist are red.
+
+use std::collections::BTreeSet;
+use super::{Game, GameItem};
+use super::check::{START_ROOM, describe_requirements, gotos, reachable_rooms};
+
+struct Edge {
+  from: String,
+  to: String,
+  label: String,
+}
+
+// A string in double quotes that Graphviz reads back as it is.
+fn quote(text: &str) -> String {
+  format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn label(section: String, requirements: &[GameItem], with_requirements: bool) -> String {
+  if with_requirements && !requirements.is_empty() {
+    format!("{} {}", section, describe_requirements(requirements))
+  } else {
+    section
+  }
+}
+
+pub fn to_dot(game: &Game, with_requirements: bool) -> String {
+  let mut room_names: Vec<&String> = game.rooms.keys().filter(|name| !game.rooms[*name].0.is_empty()).collect();
+  room_names.sort();
+
+  // In the order of the script within each room. The same goto of sections that only differ in
+  // their requirements is one edge without them.
+  let mut edges: Vec<Edge> = Vec::new();
+  for room in room_names.iter() {
+    let (rooms, actions) = &game.rooms[*room];
+    let sections = rooms.iter().map(|r| (format!("ROOM [{}]", room), &r.requirements, &r.scope))
+      .chain(actions.iter().map(|a| (format!("{} [{}]", a.action.to_string().to_uppercase(), a.name.to_string().to_lowercase()), &a.requirements, &a.scope)));
+    for (section, requirements, scope) in sections {
+      let label = label(section, requirements, with_requirements);
+      for target in gotos(scope) {
+        let to = target.to_string();
+        if !edges.iter().any(|e| &e.from == *room && e.to == to && e.label == label) {
+          edges.push(Edge { from: room.to_string(), to, label: label.clone() });
+        }
+      }
+    }
+  }
+
+  let reached = reachable_rooms(game);
+  let unknown: BTreeSet<&String> = edges.iter().map(|e| &e.to).filter(|to| game.rooms.get(*to).is_none_or(|(rooms, _a)| rooms.is_empty())).collect();
+
+  let mut dot = String::from("digraph story {\n  node [shape=box];\n");
+  for room in room_names.iter() {
+    let style = match (room.as_str() == START_ROOM, reached.contains(*room)) {
+      (true, _) => " [style=bold]",
+      (false, true) => "",
+      (false, false) => " [style=dashed]",
+    };
+    dot.push_str(&format!("  {}{};\n", quote(room), style));
+  }
+  for room in unknown.iter() {
+    dot.push_str(&format!("  {} [color=red];\n", quote(room)));
+  }
+  for edge in edges.iter() {
+    dot.push_str(&format!("  {} -> {} [label={}];\n", quote(&edge.from), quote(&edge.to), quote(&edge.label)));
+  }
+  dot.push_str("}\n");
+  dot
+}
@@ -0,0 +1,80 @@
+// The turn history behind the 'undo' and 'redo' commands.
+//
+// Every GameResult already holds a full GameState, so the history only needs to remember the
+// state from before each turn. A turn that reaches a '|NOUNDO|' in the script forgets everything
+// before it, so the player cannot take back a death scene or any other point of no return.
+
+use wasm_bindgen::prelude::*;
+use super::{GameEvent, GameResult, GameState};
+
+// How many turns can be undone when no depth is given.
+pub const DEFAULT_UNDO_DEPTH: usize = 50;
+
+#[wasm_bindgen]
+pub struct TurnHistory {
+  depth: usize,
+  past: Vec<GameState>,
+  future: Vec<GameState>,
+}
+
+#[wasm_bindgen]
+impl TurnHistory {
+  #[wasm_bindgen(constructor)]
+  pub fn new(depth: usize) -> TurnHistory {
+    TurnHistory {
+      depth,
+      past: Vec::new(),
+      future: Vec::new(),
+    }
+  }
+
+  // Remembers the state from before a turn. Turns that leave the state as it was, such as an
+  // invalid command, are not worth undoing and are skipped.
+  pub fn record(&mut self, before: &GameState, result: &GameResult) {
+    if result.get_events().iter().any(|e| matches!(e, GameEvent::NoUndo)) {
+      self.clear();
+      return;
+    }
+    if before.eq(result.get_state()) {
+      return;
+    }
+
+    self.future.clear();
+    self.past.push(before.clone());
+    if self.past.len() > self.depth {
+      self.past.remove(0);
+    }
+  }
+
+  // Returns the state from before the last turn, or nothing if there is no turn to undo.
+  pub fn undo(&mut self, current: &GameState) -> Option<GameState> {
+    let state = self.past.pop()?;
+    self.future.push(current.clone());
+    Some(state)
+  }
+
+  pub fn redo(&mut self, current: &GameState) -> Option<GameState> {
+    let state = self.future.pop()?;
+    self.past.push(current.clone());
+    Some(state)
+  }
+
+  pub fn can_undo(&self) -> bool {
+    !self.past.is_empty()
+  }
+
+  pub fn can_redo(&self) -> bool {
+    !self.future.is_empty()
+  }
+
+  pub fn clear(&mut self) {
+    self.past.clear();
+    self.future.clear();
+  }
+}
+
+impl Default for TurnHistory {
+  fn default() -> TurnHistory {
+    TurnHistory::new(DEFAULT_UNDO_DEPTH)
+  }
+}
@@ -0,0 +1,371 @@
+// JSON serialization of the compiled game, used by the web frontend and by any external tools
+// that want to read the game data.
+//
+// Schema
+// ------
+// Token        "glass"
+//                The text of the token, its position in the program is not exported.
+// Item         { "name": Token, "inventory": "personal" | "room" | "global",
+//                "action": "add" | "remove" | "check" }
+// Node         { "type": "break" }
+//              { "type": "no_undo" }
+//              { "type": "ending" }
+//              { "type": "delay", "millis": u32 }
+//              { "type": "goto", "room": Token }
+//              { "type": "text", "text": string, "spans": [Span], "italic": bool, "bold": bool, "color": u32 }
+//              { "type": "audio", "path": Token, "sound_effect": bool, "sound": Token | null,
+//                "volume": f32, "loop": bool, "channel": string, "fade": u32, "caption": string | null }
+//                The sound is the id from the SOUNDS section that the path was looked up from.
+//              { "type": "stop", "channel": string, "fade": u32 }
+//              { "type": "require", "item": Item }
+//              { "type": "modify", "item": Item }
+//              { "type": "room", "room": Room }
+//              { "type": "action", "action": Action }
+//              { "type": "test", "test": Test }
+//              { "type": "sounds", "sounds": [Sound] }
+// Room         { "name": Token, "requirements": [Item], "priority": number, "scope": [Node] }
+// Action       { "action": Token, "name": Token, "requirements": [Item], "priority": number, "scope": [Node] }
+// Test         { "name": Token, "scope": [Node] }
+// Sound        { "id": Token, "path": Token, "volume": f32 | null, "loop": bool | null,
+//                "channel": string | null, "fade": u32 | null, "caption": string | null }
+// Game         { "rooms": { <room name>: { "variants": [Room], "actions": [Action] } } }
+//                TEST sections are left out, so editing one does not invalidate saves.
+// Span         { "type": "text", "text": string }
+//              { "type": "interactable", "text": string, "noun": string }
+//                An interactable is a highlighted '#noun#', the noun is what a command would name,
+//                ie "west" for "#go west#".
+// Event        { "type": "text", "text": string, "spans": [Span], "italic": bool, "bold": bool, "color": u32 }
+//                The text keeps the '#..#' markup, the spans leave it out.
+//              { "type": "break" }
+//              { "type": "delay", "millis": u32 }
+//              { "type": "music", "path": string, "volume": f32, "loop": bool, "channel": string,
+//                "fade": u32, "caption": string | null }
+//              { "type": "sound_effect", "path": string, "volume": f32, "loop": bool,
+//                "channel": string, "fade": u32, "caption": string | null }
+//                With captions on these are text events instead, see Game::set_captions.
+//              { "type": "stop", "channel": string, "fade": u32 }
+//              { "type": "room_change", "room": string }
+//              { "type": "inventory_change", "item": Item }
+//              { "type": "no_undo" }
+//              { "type": "error", "message": string }
+// Inventory    { "personal": [string], "room": { <room name>: [string] }, "global": [string] }
+// State        { "room": string, "room_index": usize, "inventory": Inventory, "audio": { <channel>: Cue } }
+//                The audio is the looping music left playing on each channel.
+// Cue          { "path": string, "volume": f32, "loop": bool, "channel": string, "fade": u32,
+//                "caption": string | null }
+// Result       { "events": [Event], "state": State }
+//
+// Every list of item names is sorted and the rooms of a Game are ordered by name, so the same
+// game or state always produces the same text.
+
+use std::collections::BTreeMap;
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use wasm_bindgen::prelude::*;
+use super::{AudioCue, Expr, Game, GameAction, GameAudio, GameEvent, GameItem, GameResult, GameRoom, GameSound, GameState, GameTest, GameText, TextSpan,
+            InventoryAction, InventoryKind, ParseNode, Token, delay_millis, inventory_kind_to_string, text_spans, text_to_string};
+
+impl Serialize for Token {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&self.to_string())
+  }
+}
+
+impl Serialize for InventoryKind {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(inventory_kind_to_string(self))
+  }
+}
+
+impl Serialize for InventoryAction {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let action = match self {
+      InventoryAction::Add => "add",
+      InventoryAction::Remove => "remove",
+      InventoryAction::Check => "check",
+    };
+    serializer.serialize_str(action)
+  }
+}
+
+impl Serialize for GameItem {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(3))?;
+    map.serialize_entry("name", &self.name)?;
+    map.serialize_entry("inventory", &self.inventory)?;
+    map.serialize_entry("action", &self.action)?;
+    map.end()
+  }
+}
+
+fn serialize_text<M: SerializeMap>(map: &mut M, game_text: &GameText) -> Result<(), M::Error> {
+  map.serialize_entry("text", &text_to_string(game_text))?;
+  map.serialize_entry("spans", &text_spans(game_text))?;
+  map.serialize_entry("italic", &game_text.itallic)?;
+  map.serialize_entry("bold", &game_text.bold)?;
+  map.serialize_entry("color", &game_text.color)
+}
+
+fn serialize_audio<M: SerializeMap>(map: &mut M, game_audio: &GameAudio) -> Result<(), M::Error> {
+  map.serialize_entry("path", &game_audio.path)?;
+  map.serialize_entry("sound_effect", &game_audio.sound_effect)?;
+  map.serialize_entry("sound", &game_audio.sound)?;
+  map.serialize_entry("volume", &game_audio.volume)?;
+  map.serialize_entry("loop", &game_audio.looping)?;
+  map.serialize_entry("channel", &game_audio.channel)?;
+  map.serialize_entry("fade", &game_audio.fade)?;
+  map.serialize_entry("caption", &game_audio.caption)
+}
+
+fn serialize_cue<M: SerializeMap>(map: &mut M, cue: &AudioCue) -> Result<(), M::Error> {
+  map.serialize_entry("path", &cue.path)?;
+  map.serialize_entry("volume", &cue.volume)?;
+  map.serialize_entry("loop", &cue.looping)?;
+  map.serialize_entry("channel", &cue.channel)?;
+  map.serialize_entry("fade", &cue.fade)?;
+  map.serialize_entry("caption", &cue.caption)
+}
+
+impl Serialize for Expr {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(None)?;
+    match self {
+      Expr::Break => map.serialize_entry("type", "break")?,
+      Expr::NoUndo => map.serialize_entry("type", "no_undo")?,
+      Expr::Ending => map.serialize_entry("type", "ending")?,
+      Expr::Delay(token) => {
+        map.serialize_entry("type", "delay")?;
+        map.serialize_entry("millis", &delay_millis(token))?;
+      },
+      Expr::Goto(token) => {
+        map.serialize_entry("type", "goto")?;
+        map.serialize_entry("room", token)?;
+      },
+      Expr::Text(game_text) => {
+        map.serialize_entry("type", "text")?;
+        serialize_text(&mut map, game_text)?;
+      },
+      Expr::Audio(game_audio) => {
+        map.serialize_entry("type", "audio")?;
+        serialize_audio(&mut map, game_audio)?;
+      },
+      Expr::Stop(game_stop) => {
+        map.serialize_entry("type", "stop")?;
+        map.serialize_entry("channel", &game_stop.channel)?;
+        map.serialize_entry("fade", &game_stop.fade)?;
+      },
+      Expr::Require(game_item) => {
+        map.serialize_entry("type", "require")?;
+        map.serialize_entry("item", game_item)?;
+      },
+      Expr::Modify(game_item) => {
+        map.serialize_entry("type", "modify")?;
+        map.serialize_entry("item", game_item)?;
+      },
+      Expr::Room(game_room) => {
+        map.serialize_entry("type", "room")?;
+        map.serialize_entry("room", game_room)?;
+      },
+      Expr::Action(game_action) => {
+        map.serialize_entry("type", "action")?;
+        map.serialize_entry("action", game_action)?;
+      },
+      Expr::Test(game_test) => {
+        map.serialize_entry("type", "test")?;
+        map.serialize_entry("test", game_test)?;
+      },
+      Expr::Sounds(game_sounds) => {
+        map.serialize_entry("type", "sounds")?;
+        map.serialize_entry("sounds", game_sounds)?;
+      },
+    }
+    map.end()
+  }
+}
+
+// The parser never gives a node children, so a node is written out as just its expression.
+impl Serialize for ParseNode {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    self.value.serialize(serializer)
+  }
+}
+
+impl Serialize for GameRoom {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(4))?;
+    map.serialize_entry("name", &self.name)?;
+    map.serialize_entry("requirements", &self.requirements)?;
+    map.serialize_entry("priority", &self.priority)?;
+    map.serialize_entry("scope", &self.scope)?;
+    map.end()
+  }
+}
+
+impl Serialize for GameAction {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(5))?;
+    map.serialize_entry("action", &self.action)?;
+    map.serialize_entry("name", &self.name)?;
+    map.serialize_entry("requirements", &self.requirements)?;
+    map.serialize_entry("priority", &self.priority)?;
+    map.serialize_entry("scope", &self.scope)?;
+    map.end()
+  }
+}
+
+impl Serialize for GameTest {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(2))?;
+    map.serialize_entry("name", &self.name)?;
+    map.serialize_entry("scope", &self.scope)?;
+    map.end()
+  }
+}
+
+impl Serialize for GameSound {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(7))?;
+    map.serialize_entry("id", &self.id)?;
+    map.serialize_entry("path", &self.path)?;
+    map.serialize_entry("volume", &self.options.volume)?;
+    map.serialize_entry("loop", &self.options.looping)?;
+    map.serialize_entry("channel", &self.options.channel)?;
+    map.serialize_entry("fade", &self.options.fade)?;
+    map.serialize_entry("caption", &self.options.caption)?;
+    map.end()
+  }
+}
+
+struct RoomGroup<'a> {
+  variants: &'a Vec<GameRoom>,
+  actions: &'a Vec<GameAction>,
+}
+
+impl<'a> Serialize for RoomGroup<'a> {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(2))?;
+    map.serialize_entry("variants", self.variants)?;
+    map.serialize_entry("actions", self.actions)?;
+    map.end()
+  }
+}
+
+impl Serialize for Game {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let rooms: BTreeMap<&String, RoomGroup> = self.rooms.iter()
+      .map(|(name, (variants, actions))| (name, RoomGroup { variants, actions }))
+      .collect();
+    let mut map = serializer.serialize_map(Some(1))?;
+    map.serialize_entry("rooms", &rooms)?;
+    map.end()
+  }
+}
+
+impl Serialize for TextSpan {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(None)?;
+    match self {
+      TextSpan::Text(text) => {
+        map.serialize_entry("type", "text")?;
+        map.serialize_entry("text", text)?;
+      },
+      TextSpan::Interactable { text, noun } => {
+        map.serialize_entry("type", "interactable")?;
+        map.serialize_entry("text", text)?;
+        map.serialize_entry("noun", noun)?;
+      },
+    }
+    map.end()
+  }
+}
+
+impl Serialize for GameEvent {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(None)?;
+    match self {
+      GameEvent::Text { text, spans, italic, bold, color } => {
+        map.serialize_entry("type", "text")?;
+        map.serialize_entry("text", text)?;
+        map.serialize_entry("spans", spans)?;
+        map.serialize_entry("italic", italic)?;
+        map.serialize_entry("bold", bold)?;
+        map.serialize_entry("color", color)?;
+      },
+      GameEvent::Break => map.serialize_entry("type", "break")?,
+      GameEvent::NoUndo => map.serialize_entry("type", "no_undo")?,
+      GameEvent::Delay(ms) => {
+        map.serialize_entry("type", "delay")?;
+        map.serialize_entry("millis", ms)?;
+      },
+      GameEvent::Music(cue) => {
+        map.serialize_entry("type", "music")?;
+        serialize_cue(&mut map, cue)?;
+      },
+      GameEvent::SoundEffect(cue) => {
+        map.serialize_entry("type", "sound_effect")?;
+        serialize_cue(&mut map, cue)?;
+      },
+      GameEvent::Stop { channel, fade } => {
+        map.serialize_entry("type", "stop")?;
+        map.serialize_entry("channel", channel)?;
+        map.serialize_entry("fade", fade)?;
+      },
+      GameEvent::RoomChange(room) => {
+        map.serialize_entry("type", "room_change")?;
+        map.serialize_entry("room", room)?;
+      },
+      GameEvent::InventoryChange(game_item) => {
+        map.serialize_entry("type", "inventory_change")?;
+        map.serialize_entry("item", game_item)?;
+      },
+      GameEvent::Error(msg) => {
+        map.serialize_entry("type", "error")?;
+        map.serialize_entry("message", msg)?;
+      },
+    }
+    map.end()
+  }
+}
+
+impl Serialize for GameResult {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(2))?;
+    map.serialize_entry("events", &self.events)?;
+    map.serialize_entry("state", &self.state)?;
+    map.end()
+  }
+}
+
+// Serializing can only fail for maps with non-string keys, which none of the game types have.
+pub fn to_json<T: Serialize>(value: &T) -> String {
+  serde_json::to_string(value).expect("ICE: Failed to serialize the game to JSON")
+}
+
+#[wasm_bindgen]
+impl Game {
+  pub fn to_json(&self) -> String {
+    to_json(self)
+  }
+
+  // The variant of the room that the state would enter.
+  pub fn room_json(&self, room_name: String, state: &GameState) -> Result<String, String> {
+    self.print_room(&room_name, state)
+  }
+}
+
+#[wasm_bindgen]
+impl GameResult {
+  pub fn to_json(&self) -> String {
+    to_json(self)
+  }
+}
+
+#[wasm_bindgen]
+impl GameState {
+  pub fn to_json(&self) -> String {
+    to_json(self)
+  }
+
+  pub fn from_json(text: String) -> Result<GameState, String> {
+    serde_json::from_str(&text).map_err(|msg| format!("Invalid game state: {}", msg))
+  }
+}
@@ -3,14 +3,28 @@ extern crate cfg_if;
 
 use std::cmp;
 use std::path::Path;
-use std::fs::File;
 use std::io::prelude::*;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
-use std::collections::HashSet;
 use std::{thread, time};
 use cfg_if::cfg_if;
+use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
+pub mod check;
+pub mod cli;
+pub mod coverage;
+pub mod dot;
+pub mod history;
+pub mod json;
+pub mod playtest;
+pub mod save;
+pub mod session;
+pub mod solver;
+pub mod terminal;
+pub mod transcript;
+
 cfg_if! {
     // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
     // allocator.
@@ -91,6 +105,14 @@ pub struct InventoryModification {
   pub item: Token,
 }
 
+// A piece of a line of text, so that frontends can tell the highlighted nouns from the rest.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextSpan {
+  Text(String),
+  // A '#noun#' the player can interact with, linked to the noun a command would name.
+  Interactable { text: String, noun: String },
+}
+
 #[derive(Debug, Clone)]
 pub struct GameText {
   pub text: Vec<Token>,
@@ -99,10 +121,79 @@ pub struct GameText {
   pub color: u32,
 }
 
+// The options that can follow the path of a sound, left out when the script does not set them.
+#[derive(Debug, Clone, Default)]
+pub struct AudioOptions {
+  pub volume: Option<f32>,
+  pub looping: Option<bool>,
+  pub channel: Option<String>,
+  pub fade: Option<u32>,
+  pub caption: Option<String>,
+}
+
+impl AudioOptions {
+  // The options with the ones set in the other options replacing them.
+  fn overlay(&self, other: &AudioOptions) -> AudioOptions {
+    AudioOptions {
+      volume: other.volume.or(self.volume),
+      looping: other.looping.or(self.looping),
+      channel: other.channel.clone().or_else(|| self.channel.clone()),
+      fade: other.fade.or(self.fade),
+      caption: other.caption.clone().or_else(|| self.caption.clone()),
+    }
+  }
+}
+
 #[derive(Debug, Clone)]
 pub struct GameAudio {
   pub path: Token,
   pub sound_effect: bool,
+  // The id that the path was looked up from in the SOUNDS section, if it was not a path.
+  pub sound: Option<Token>,
+  // The options written where the sound is played, they take over from the SOUNDS section.
+  pub options: AudioOptions,
+  pub volume: f32,
+  pub looping: bool,
+  pub channel: String,
+  // The milliseconds the sound fades in over, music it replaces fades out over the same time.
+  pub fade: u32,
+  // What the sound tells the player, for those who cannot hear it.
+  pub caption: Option<String>,
+}
+
+impl GameAudio {
+  // By default music loops on the 'music' channel and sound effects play once on 'effects'.
+  fn new(path: Token, sound_effect: bool, sound: Option<Token>, options: AudioOptions, defaults: &AudioOptions) -> GameAudio {
+    let resolved = defaults.overlay(&options);
+    let channel = if sound_effect { "effects" } else { "music" };
+    GameAudio {
+      path,
+      sound_effect,
+      sound,
+      options,
+      volume: resolved.volume.unwrap_or(1.0),
+      looping: resolved.looping.unwrap_or(!sound_effect),
+      channel: resolved.channel.unwrap_or_else(|| channel.to_string()),
+      fade: resolved.fade.unwrap_or(0),
+      caption: resolved.caption,
+    }
+  }
+}
+
+// An entry of the SOUNDS section.
+#[derive(Debug, Clone)]
+pub struct GameSound {
+  pub id: Token,
+  pub path: Token,
+  pub options: AudioOptions,
+}
+
+// Stops what is playing on the channel, fading it out over the milliseconds if there are any.
+#[derive(Debug, Clone)]
+pub struct GameStop {
+  pub token: Token,
+  pub channel: String,
+  pub fade: u32,
 }
 
 #[derive(Debug, Clone)]
@@ -117,6 +208,8 @@ pub struct GameAction {
   pub action: Token,
   pub name: Token,
   pub requirements: Vec<GameItem>,
+  // Set by '|PRIORITY n|' after the requirements, see choose_section.
+  pub priority: u32,
   pub scope: Vec<ParseNode>,
 }
 
@@ -124,6 +217,15 @@ pub struct GameAction {
 pub struct GameRoom {
   pub name: Token,
   pub requirements: Vec<GameItem>,
+  pub priority: u32,
+  pub scope: Vec<ParseNode>,
+}
+
+// A playthrough written into the script. The scope holds the commands to type as text, the
+// rooms to end up in as gotos and the items to have as requirements.
+#[derive(Debug, Clone)]
+pub struct GameTest {
+  pub name: Token,
   pub scope: Vec<ParseNode>,
 }
 
@@ -132,12 +234,18 @@ pub struct GameRoom {
 #[derive(Debug, Clone)]
 pub enum Expr {
   Break,
+  NoUndo,
+  // Marks the room as one of the endings the story is meant to reach, see solver.rs.
+  Ending,
   Delay(Token),
   Room(GameRoom),
   Goto(Token),
   Text(GameText),
   Audio(GameAudio),
+  Stop(GameStop),
   Action(GameAction),
+  Test(GameTest),
+  Sounds(Vec<GameSound>),
   Require(GameItem),
   Modify(GameItem),
 }
@@ -167,13 +275,18 @@ pub struct Program {
 
 fn expr_to_string(expr: &Expr) -> String {
   match expr {
-    Expr::Break => format!("|BREAK|"),
-    Expr::Delay(token) => format!("{}", token_kind_to_string(&token.kind)),
+    Expr::Break => "|BREAK|".to_string(),
+    Expr::NoUndo => "|NOUNDO|".to_string(),
+    Expr::Ending => "|ENDING|".to_string(),
+    Expr::Delay(token) => token_kind_to_string(&token.kind).to_string(),
     Expr::Room(game_room) => format!("Room |{}|", game_room.name.to_string()),
     Expr::Goto(token) => format!("[[{}]]", token_kind_to_string(&token.kind)),
-    Expr::Text(game_text) => format!("{}", (&game_text.text).into_iter().map(|t| -> String { t.to_string() }).collect::<String>()),
+    Expr::Text(game_text) => game_text.text.iter().map(|t| -> String { t.to_string() }).collect::<String>(),
     Expr::Audio(game_audio) => format!("<{}>", game_audio.path.to_string()),
+    Expr::Stop(game_stop) => format!("|{}|", game_stop.token.to_string()),
     Expr::Action(game_action) => format!("{} |{}|", game_action.action.to_string(), game_action.name.to_string()),
+    Expr::Test(game_test) => format!("TEST |{}|", game_test.name.to_string()),
+    Expr::Sounds(_game_sounds) => "SOUNDS".to_string(),
     Expr::Require(game_item) => format!("REQUIRE({})", game_item.name.to_string()),
     Expr::Modify(game_item) => format!("MODIFY({})", game_item.name.to_string()),
   }
@@ -181,63 +294,120 @@ fn expr_to_string(expr: &Expr) -> String {
 
 fn token_kind_to_string(kind: &TokenKind) -> String {
   match kind {
-    TokenKind::Text(t) => format!("{}", t),
-    TokenKind::Keyword(t) => format!("{}", t),
-    TokenKind::Ampersand => format!("&"),
-    TokenKind::Asterisk => format!("*"),
-    TokenKind::At => format!("@"),
-    TokenKind::Caret => format!("^"),
-    TokenKind::CloseCurlyBrace => format!("}}"),
-    TokenKind::CloseParen => format!(")"),
-    TokenKind::CloseSquareBracket => format!("]"),
-    TokenKind::Dollar => format!("$"),
-    TokenKind::GreaterThan => format!(">"),
-    TokenKind::LessThan => format!("<"),
-    TokenKind::Minus => format!("-"),
-    TokenKind::OpenCurlyBrace => format!("{{"),
-    TokenKind::OpenParen => format!("("),
-    TokenKind::OpenSquareBracket => format!("["),
-    TokenKind::Percent => format!("%"),
-    TokenKind::Pipe => format!("|"),
-    TokenKind::Plus => format!("+"),
-    TokenKind::Pound => format!("#"),
-    TokenKind::Semicolon => format!(";"),
-    TokenKind::Tilde => format!("~"),
-    TokenKind::Newline => format!("\n"),
+    TokenKind::Text(t) => t.to_string(),
+    TokenKind::Keyword(t) => t.to_string(),
+    TokenKind::Ampersand => "&".to_string(),
+    TokenKind::Asterisk => "*".to_string(),
+    TokenKind::At => "@".to_string(),
+    TokenKind::Caret => "^".to_string(),
+    TokenKind::CloseCurlyBrace => "}".to_string(),
+    TokenKind::CloseParen => ")".to_string(),
+    TokenKind::CloseSquareBracket => "]".to_string(),
+    TokenKind::Dollar => "$".to_string(),
+    TokenKind::GreaterThan => ">".to_string(),
+    TokenKind::LessThan => "<".to_string(),
+    TokenKind::Minus => "-".to_string(),
+    TokenKind::OpenCurlyBrace => "{".to_string(),
+    TokenKind::OpenParen => "(".to_string(),
+    TokenKind::OpenSquareBracket => "[".to_string(),
+    TokenKind::Percent => "%".to_string(),
+    TokenKind::Pipe => "|".to_string(),
+    TokenKind::Plus => "+".to_string(),
+    TokenKind::Pound => "#".to_string(),
+    TokenKind::Semicolon => ";".to_string(),
+    TokenKind::Tilde => "~".to_string(),
+    TokenKind::Newline => "\n".to_string(),
   }
 }
 
 #[allow(dead_code)]
 fn match_token_kind(kind_a: &TokenKind, kind_b: &TokenKind) -> bool {
   match (kind_a, kind_b) {
-    (TokenKind::Text(t_a), TokenKind::Text(t_b)) => if t_a == t_b { return true } else { return false },
-    (TokenKind::Keyword(t_a), TokenKind::Keyword(t_b)) => if t_a == t_b { return true } else { return false },
-    _ => if kind_a == kind_b { return true } else { return false },
+    (TokenKind::Text(t_a), TokenKind::Text(t_b)) => t_a == t_b,
+    (TokenKind::Keyword(t_a), TokenKind::Keyword(t_b)) => t_a == t_b,
+    _ => kind_a == kind_b,
   }
 }
 
-fn tokens_to_string(tokens: &Vec<Token>) -> String {
-  tokens.into_iter().map(|t| -> String { t.to_string() }).collect::<String>()
+fn tokens_to_string(tokens: &[Token]) -> String {
+  tokens.iter().map(|t| -> String { t.to_string() }).collect::<String>()
 }
 
-fn path_exists(path: &String) -> bool {
-  Path::new(path).exists()
+// The text of a line with its '#..#' markup. The lexer splits a line around highlighted nouns and
+// wherever it skips a character, ie '”', so a space is put back where the tokens were apart.
+fn text_to_string(game_text: &GameText) -> String {
+  let mut text = String::new();
+  let mut end = None;
+  for token in game_text.text.iter() {
+    let string = token.to_string();
+    if end.is_some_and(|end| end < token.index) {
+      text.push(' ');
+    }
+    end = Some(token.index + string.chars().count());
+    text.push_str(&string);
+  }
+  text
+}
+
+// The text of a line split into plain text and the nouns highlighted by '#..#'.
+fn text_spans(game_text: &GameText) -> Vec<TextSpan> {
+  let mut spans = Vec::new();
+  let mut text = String::new();
+  let mut highlighted = false;
+  let mut end = None;
+  for token in game_text.text.iter() {
+    let string = token.to_string();
+    if !highlighted && end.is_some_and(|end| end < token.index) {
+      text.push(' ');
+    }
+    end = Some(token.index + string.chars().count());
+    match (&token.kind, highlighted) {
+      (TokenKind::Pound, false) => {
+        if !text.is_empty() {
+          spans.push(TextSpan::Text(std::mem::take(&mut text)));
+        }
+        highlighted = true;
+      },
+      (TokenKind::Pound, true) => highlighted = false,
+      (_, true) => spans.push(TextSpan::Interactable { noun: interactable_noun(&string), text: string }),
+      (_, false) => text.push_str(&string),
+    }
+  }
+  if !text.is_empty() {
+    spans.push(TextSpan::Text(text));
+  }
+  spans
+}
+
+// The noun a command typed from the highlighted text would name, ie 'glass' for '#glass#' and
+// 'west' for '#go west#'.
+fn interactable_noun(text: &str) -> String {
+  match parse_command(text) {
+    (_action, noun) if !noun.is_empty() => noun,
+    _ => text.trim().to_lowercase(),
+  }
 }
 
 #[wasm_bindgen]
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Inventory {
-  personal: HashSet<String>,
-  room: HashMap<String, HashSet<String>>,
-  global: HashSet<String>,
+  personal: BTreeSet<String>,
+  room: BTreeMap<String, BTreeSet<String>>,
+  global: BTreeSet<String>,
+}
+
+impl Default for Inventory {
+  fn default() -> Self {
+    Self::new()
+  }
 }
 
 impl Inventory {
   pub fn new() -> Inventory {
     Inventory {
-      personal: HashSet::new(),
-      room: HashMap::new(),
-      global: HashSet::new(),
+      personal: BTreeSet::new(),
+      room: BTreeMap::new(),
+      global: BTreeSet::new(),
     }
   }
 
@@ -256,8 +426,8 @@ impl Inventory {
   // Check is the item is in the inventory.
   pub fn check_item(&self, item: &GameItem, room: &String) -> bool {
     match &item.inventory {
-      InventoryKind::Personal => { return self.personal.contains(&item.name.to_string()) },
-      InventoryKind::Global => { return self.global.contains(&item.name.to_string()) },
+      InventoryKind::Personal => { self.personal.contains(&item.name.to_string()) },
+      InventoryKind::Global => { self.global.contains(&item.name.to_string()) },
       InventoryKind::Room => {
         match self.room.get(room) {
           Some(room_inventory) => room_inventory.contains(&item.name.to_string()),
@@ -268,13 +438,8 @@ impl Inventory {
   }
 
   // Must have all items for check to pass.
-  pub fn check_items(&self, items: &Vec<GameItem>, room: &String) -> bool {
-    for i in 0..items.len() {
-      if !self.check_item(&items[i], &room) {
-        return false;
-      }
-    }
-    true
+  pub fn check_items(&self, items: &[GameItem], room: &String) -> bool {
+    items.iter().all(|item| self.check_item(item, room))
   }
 
   pub fn add_item(&self, item: &GameItem, room_name: &String) -> Inventory {
@@ -285,9 +450,9 @@ impl Inventory {
         let room = self.room.clone();
         personal.insert(item.name.to_string()); 
         Inventory {
-          personal: personal,
-          global: global,
-          room: room,
+          personal,
+          global,
+          room,
         }
       },
       InventoryKind::Global => { 
@@ -296,9 +461,9 @@ impl Inventory {
         let room = self.room.clone();
         global.insert(item.name.to_string()); 
         Inventory {
-          personal: personal,
-          global: global,
-          room: room,
+          personal,
+          global,
+          room,
         }
       },
       InventoryKind::Room => {
@@ -308,15 +473,15 @@ impl Inventory {
         match room.get_mut(room_name) {
           Some(room_inventory) => { room_inventory.insert(item.name.to_string()); },
           None => {
-            let mut new_inventory = HashSet::new();
+            let mut new_inventory = BTreeSet::new();
             new_inventory.insert(item.name.to_string());
             room.insert(room_name.to_string(), new_inventory);
           },
         }
         Inventory {
-          personal: personal,
-          global: global,
-          room: room,
+          personal,
+          global,
+          room,
         }
       },
     }
@@ -330,9 +495,9 @@ impl Inventory {
         let room = self.room.clone();
         personal.remove(&item.name.to_string()); 
         Inventory {
-          personal: personal,
-          global: global,
-          room: room,
+          personal,
+          global,
+          room,
         }
       },
       InventoryKind::Global => { 
@@ -341,23 +506,22 @@ impl Inventory {
         let room = self.room.clone();
         global.remove(&item.name.to_string()); 
         Inventory {
-          personal: personal,
-          global: global,
-          room: room,
+          personal,
+          global,
+          room,
         }
       },
       InventoryKind::Room => {
         let personal = self.personal.clone(); 
         let global = self.global.clone(); 
         let mut room = self.room.clone();
-        match room.get_mut(room_name) {
-          Some(room_inventory) => { room_inventory.remove(&item.name.to_string()); },
-          None => (),
+        if let Some(room_inventory) = room.get_mut(room_name) {
+          room_inventory.remove(&item.name.to_string());
         }
         Inventory {
-          personal: personal,
-          global: global,
-          room: room,
+          personal,
+          global,
+          room,
         }
       }
     }
@@ -365,8 +529,8 @@ impl Inventory {
 
   pub fn modify(&self, item: &GameItem, room: &String) -> Inventory {
     match item.action {
-      InventoryAction::Add => self.add_item(&item, &room),
-      InventoryAction::Remove => self.remove_item(&item, &room),
+      InventoryAction::Add => self.add_item(item, room),
+      InventoryAction::Remove => self.remove_item(item, room),
       InventoryAction::Check => panic!("ICE: Attempting to modify a check item '{}'", item.name.to_string()),
     }
   }
@@ -379,27 +543,40 @@ impl Inventory {
          )
   }
 
-  pub fn eq(&self, other: &Inventory) -> bool {
+}
+
+impl PartialEq for Inventory {
+  fn eq(&self, other: &Inventory) -> bool {
     self.personal == other.personal
       && self.global == other.global
       && self.rooms_eq(other) 
   }
 }
 
+// How many gotos can be followed in a single turn before the rooms are assumed to loop forever.
+const MAX_GOTO_DEPTH: usize = 64;
+
 #[wasm_bindgen]
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct GameState {
-  inventory: Inventory,
+  #[serde(rename = "room")]
   current_room_name: String,
+  #[serde(rename = "room_index")]
   current_room_index: usize,
+  inventory: Inventory,
+  // The looping music playing on each channel, so it can be played again when the state is
+  // restored. Saves from before it was kept start in silence.
+  #[serde(default)]
+  audio: BTreeMap<String, AudioCue>,
 }
 
 impl GameState {
   pub fn new(inventory: Inventory, room_name: String, room_index: usize) -> GameState {
     GameState {
-      inventory: inventory,
+      inventory,
       current_room_name: room_name,
       current_room_index: room_index,
+      audio: BTreeMap::new(),
     }
   }
 
@@ -431,46 +608,375 @@ impl GameState {
     self.current_room_index = index;
   }
 
-  pub fn eq(&self, other: &GameState) -> bool {
+  pub fn get_audio(&self) -> &BTreeMap<String, AudioCue> {
+    &self.audio
+  }
+
+  // Keeps track of the music that an audio event leaves playing. Sound effects are not kept, they
+  // are over by the time the state could be restored.
+  fn play(&mut self, event: &GameEvent) {
+    match event {
+      GameEvent::Music(cue) if cue.looping => { self.audio.insert(cue.channel.clone(), cue.clone()); },
+      GameEvent::Music(cue) => { self.audio.remove(&cue.channel); },
+      GameEvent::Stop { channel, .. } => { self.audio.remove(channel); },
+      _ => (),
+    }
+  }
+
+}
+
+impl PartialEq for GameState {
+  fn eq(&self, other: &GameState) -> bool {
     self.current_room_index == other.current_room_index
       && self.current_room_name == other.current_room_name
-      && self.inventory.eq(&other.inventory)
+      && self.inventory == other.inventory
+      && self.audio == other.audio
+  }
+}
+
+// An audio file to play and how to play it, as resolved from the SOUNDS section.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AudioCue {
+  pub path: String,
+  pub volume: f32,
+  #[serde(rename = "loop")]
+  pub looping: bool,
+  pub channel: String,
+  pub fade: u32,
+  #[serde(default)]
+  pub caption: Option<String>,
+}
+
+impl AudioCue {
+  pub fn from_audio(game_audio: &GameAudio) -> AudioCue {
+    AudioCue {
+      path: game_audio.path.to_string(),
+      volume: game_audio.volume,
+      looping: game_audio.looping,
+      channel: game_audio.channel.clone(),
+      fade: game_audio.fade,
+      caption: game_audio.caption.clone(),
+    }
+  }
+}
+
+// A single piece of output produced while running a scope, in the order that it happened
+#[derive(Debug, Clone)]
+pub enum GameEvent {
+  // The text keeps its '#..#' markup, the spans are the same text split at the highlighted nouns.
+  Text { text: String, spans: Vec<TextSpan>, italic: bool, bold: bool, color: u32 },
+  Break,
+  Delay(u32),
+  Music(AudioCue),
+  SoundEffect(AudioCue),
+  // Stop what is playing on the channel, fading it out over the milliseconds.
+  Stop { channel: String, fade: u32 },
+  RoomChange(String),
+  InventoryChange(GameItem),
+  // Nothing before this point can be undone.
+  NoUndo,
+  Error(String),
+}
+
+impl GameEvent {
+  pub fn from_text(game_text: &GameText) -> GameEvent {
+    GameEvent::Text {
+      text: text_to_string(game_text),
+      spans: text_spans(game_text),
+      italic: game_text.itallic,
+      bold: game_text.bold,
+      color: game_text.color,
+    }
+  }
+
+  // Text from the engine rather than the script, ie the inventory, which has nothing highlighted.
+  pub fn plain_text(text: String) -> GameEvent {
+    GameEvent::Text {
+      spans: vec![TextSpan::Text(text.clone())],
+      text,
+      italic: false,
+      bold: false,
+      color: 0,
+    }
+  }
+
+  pub fn kind(&self) -> GameEventKind {
+    match self {
+      GameEvent::Text { .. } => GameEventKind::Text,
+      GameEvent::Break => GameEventKind::Break,
+      GameEvent::Delay(_) => GameEventKind::Delay,
+      GameEvent::Music(_) => GameEventKind::Music,
+      GameEvent::SoundEffect(_) => GameEventKind::SoundEffect,
+      GameEvent::Stop { .. } => GameEventKind::Stop,
+      GameEvent::RoomChange(_) => GameEventKind::RoomChange,
+      GameEvent::InventoryChange(_) => GameEventKind::InventoryChange,
+      GameEvent::NoUndo => GameEventKind::NoUndo,
+      GameEvent::Error(_) => GameEventKind::Error,
+    }
+  }
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GameEventKind {
+  Text,
+  Break,
+  Delay,
+  Music,
+  SoundEffect,
+  Stop,
+  RoomChange,
+  InventoryChange,
+  NoUndo,
+  Error,
+}
+
+// The JS view of a GameEvent, wasm-bindgen cannot export enums that carry data so every
+// field is exposed as a getter that is only set for the kinds that use it.
+#[wasm_bindgen(js_name = GameEvent)]
+#[derive(Debug, Clone)]
+pub struct JsGameEvent {
+  event: GameEvent,
+}
+
+#[wasm_bindgen(js_class = GameEvent)]
+impl JsGameEvent {
+  #[wasm_bindgen(getter)]
+  pub fn kind(&self) -> GameEventKind {
+    self.event.kind()
+  }
+
+  // The prose of a Text event or the message of an Error event.
+  #[wasm_bindgen(getter)]
+  pub fn text(&self) -> Option<String> {
+    match &self.event {
+      GameEvent::Text { text, .. } => Some(text.clone()),
+      GameEvent::Error(msg) => Some(msg.clone()),
+      _ => None,
+    }
+  }
+
+  // The spans of a Text event as a JSON list of Span, see json.rs. The interactable ones are the
+  // highlighted nouns that a frontend could make clickable.
+  #[wasm_bindgen(getter)]
+  pub fn spans(&self) -> Option<String> {
+    match &self.event {
+      GameEvent::Text { spans, .. } => Some(json::to_json(spans)),
+      _ => None,
+    }
+  }
+
+  #[wasm_bindgen(getter)]
+  pub fn italic(&self) -> bool {
+    matches!(self.event, GameEvent::Text { italic: true, .. })
+  }
+
+  #[wasm_bindgen(getter)]
+  pub fn bold(&self) -> bool {
+    matches!(self.event, GameEvent::Text { bold: true, .. })
+  }
+
+  #[wasm_bindgen(getter)]
+  pub fn color(&self) -> u32 {
+    match &self.event {
+      GameEvent::Text { color, .. } => *color,
+      _ => 0,
+    }
+  }
+
+  #[wasm_bindgen(getter)]
+  pub fn millis(&self) -> Option<u32> {
+    match &self.event {
+      GameEvent::Delay(ms) => Some(*ms),
+      _ => None,
+    }
+  }
+
+  // The audio file of a Music or SoundEffect event.
+  #[wasm_bindgen(getter)]
+  pub fn path(&self) -> Option<String> {
+    match &self.event {
+      GameEvent::Music(cue) | GameEvent::SoundEffect(cue) => Some(cue.path.clone()),
+      _ => None,
+    }
+  }
+
+  // The volume from 0 to 1 of a Music or SoundEffect event.
+  #[wasm_bindgen(getter)]
+  pub fn volume(&self) -> Option<f32> {
+    match &self.event {
+      GameEvent::Music(cue) | GameEvent::SoundEffect(cue) => Some(cue.volume),
+      _ => None,
+    }
+  }
+
+  // Whether the audio of a Music or SoundEffect event repeats until other music replaces it.
+  #[wasm_bindgen(getter)]
+  pub fn looping(&self) -> bool {
+    matches!(&self.event, GameEvent::Music(cue) | GameEvent::SoundEffect(cue) if cue.looping)
+  }
+
+  // The channel that a Music or SoundEffect event plays on or a Stop event stops.
+  #[wasm_bindgen(getter)]
+  pub fn channel(&self) -> Option<String> {
+    match &self.event {
+      GameEvent::Music(cue) | GameEvent::SoundEffect(cue) => Some(cue.channel.clone()),
+      GameEvent::Stop { channel, .. } => Some(channel.clone()),
+      _ => None,
+    }
+  }
+
+  // The caption of a Music or SoundEffect event, if the script gives it one.
+  #[wasm_bindgen(getter)]
+  pub fn caption(&self) -> Option<String> {
+    match &self.event {
+      GameEvent::Music(cue) | GameEvent::SoundEffect(cue) => cue.caption.clone(),
+      _ => None,
+    }
+  }
+
+  // The milliseconds that the audio fades in over, or out over for a Stop event.
+  #[wasm_bindgen(getter)]
+  pub fn fade(&self) -> u32 {
+    match &self.event {
+      GameEvent::Music(cue) | GameEvent::SoundEffect(cue) => cue.fade,
+      GameEvent::Stop { fade, .. } => *fade,
+      _ => 0,
+    }
+  }
+
+  #[wasm_bindgen(getter)]
+  pub fn room(&self) -> Option<String> {
+    match &self.event {
+      GameEvent::RoomChange(name) => Some(name.clone()),
+      _ => None,
+    }
+  }
+
+  #[wasm_bindgen(getter)]
+  pub fn item(&self) -> Option<String> {
+    match &self.event {
+      GameEvent::InventoryChange(game_item) => Some(game_item.name.to_string()),
+      _ => None,
+    }
+  }
+
+  // One of "personal", "room" or "global" for an InventoryChange event.
+  #[wasm_bindgen(getter)]
+  pub fn inventory(&self) -> Option<String> {
+    match &self.event {
+      GameEvent::InventoryChange(game_item) => Some(inventory_kind_to_string(&game_item.inventory).to_string()),
+      _ => None,
+    }
+  }
+
+  // True when an InventoryChange event adds the item, false when it removes it.
+  #[wasm_bindgen(getter)]
+  pub fn added(&self) -> bool {
+    matches!(&self.event, GameEvent::InventoryChange(game_item) if matches!(game_item.action, InventoryAction::Add))
+  }
+}
+
+fn inventory_kind_to_string(kind: &InventoryKind) -> &'static str {
+  match kind {
+    InventoryKind::Personal => "personal",
+    InventoryKind::Room => "room",
+    InventoryKind::Global => "global",
+  }
+}
+
+// Reads the number of milliseconds out of a '|DELAY xxxx|' token, defaulting to five seconds.
+fn delay_millis(token: &Token) -> u32 {
+  match token.to_string().split_whitespace().nth(1) {
+    Some(ms) => ms.parse().unwrap_or(5000),
+    None => 5000,
+  }
+}
+
+// Renders the events in the original flat format with inline '|BREAK|' and '<path>' markers.
+fn events_to_string(events: &[GameEvent]) -> String {
+  let mut output = String::new();
+  for (i, event) in events.iter().enumerate() {
+    match event {
+      GameEvent::Text { text, .. } => {
+        match events.get(i+1) {
+          Some(GameEvent::Text { .. }) => output.push_str(&format!("{} ", text)),
+          _ => output.push_str(&format!("{} \n", text)),
+        }
+      },
+      GameEvent::Break => output.push_str("|BREAK|\n"),
+      GameEvent::Delay(ms) => output.push_str(&format!("|DELAY {}|\n", ms)),
+      GameEvent::Music(cue) | GameEvent::SoundEffect(cue) => output.push_str(&format!("<{}>\n", cue.path)),
+      // The web frontend shows the string as it is, and the terminal prints its own note for a stop.
+      GameEvent::RoomChange(_) | GameEvent::InventoryChange(_) | GameEvent::NoUndo | GameEvent::Stop { .. } => (),
+      GameEvent::Error(msg) => output.push_str(msg),
+    }
   }
+  output
 }
 
 #[wasm_bindgen]
 pub struct GameResult {
-  text: String,
+  events: Vec<GameEvent>,
   state: GameState,
 }
 
 impl GameResult {
   pub fn new(text: String, state: GameState) -> GameResult {
+    GameResult::from_events(vec![GameEvent::plain_text(text)], state)
+  }
+
+  pub fn from_events(events: Vec<GameEvent>, state: GameState) -> GameResult {
     GameResult {
-      text: text,
-      state: state,
+      events,
+      state,
     }
   }
+
+  pub fn error(msg: String, state: GameState) -> GameResult {
+    GameResult::from_events(vec![GameEvent::Error(msg)], state)
+  }
+
+  pub fn get_events(&self) -> &Vec<GameEvent> {
+    &self.events
+  }
+
+  pub fn get_state(&self) -> &GameState {
+    &self.state
+  }
 }
 
 #[wasm_bindgen]
 impl GameResult {
+  // Exported to JavaScript as toString, which Display could not provide.
+  #[allow(clippy::inherent_to_string)]
   pub fn to_string(&self) -> String {
-    self.text.clone()
+    events_to_string(&self.events)
   }
 
   pub fn to_state(&self) -> GameState {
     self.state.clone()
   }
+
+  pub fn events(&self) -> Vec<JsGameEvent> {
+    self.events.iter().map(|e| JsGameEvent { event: e.clone() }).collect()
+  }
 }
 
+// The variants and actions of every room, keyed by room name.
+type RoomMap = HashMap<String, (Vec<GameRoom>, Vec<GameAction>)>;
+
 #[wasm_bindgen]
 pub struct Game {
-  rooms: HashMap<String, (Vec<GameRoom>, Vec<GameAction>)>,
+  rooms: RoomMap,
+  tests: Vec<GameTest>,
+  sounds: Vec<GameSound>,
+  // Whether audio is shown as its caption instead of being played, see set_captions.
+  captions: bool,
 }
 
 impl Game {
-  pub fn new(rooms_map: &HashMap<String, (Vec<GameRoom>, Vec<GameAction>)>) -> Game {
+  pub fn new(rooms_map: &RoomMap, tests: &[GameTest], sounds: &[GameSound]) -> Game {
     let mut new_map = HashMap::new();
     for (key, val) in rooms_map.iter() {
       let (r, a) = val;
@@ -479,61 +985,42 @@ impl Game {
 
     Game {
       rooms: new_map,
+      tests: tests.to_vec(),
+      sounds: sounds.to_vec(),
+      captions: false,
     }
   }
 
-  pub fn print_room(&self, room_name: &String, state: &GameState) -> String {
-    let room = match self.find_room(&room_name, &state) {
-      Ok((r, _a)) => r,
-      Err(msg) => panic!("{}", msg),
-    };
+  pub fn get_tests(&self) -> &Vec<GameTest> {
+    &self.tests
+  }
 
-    let mut output = "{\n  \"room_output\": [\n".to_string();
-    for i in 0..room.scope.len() {
-      match &room.scope[i].value {
-        Expr::Break => { 
-          output.push_str("    \"|BREAK|\",\n");
-        },
-        Expr::Delay(token) => { output.push_str(&format!("    \"|{}|\",\n", token.to_string())); },  // TODO: actually read delay.
-        Expr::Room(game_room) => { panic!("Discovered Room '{}' inside of Room '{}'", game_room.name.to_string(), state.get_room_name()); },
-        Expr::Goto(token) => {
-          output.push_str(&format!("    \"[[{}]]\",\n", token.to_string()));
-        },
-        Expr::Text(game_text) => {
-          output.push_str(&format!("    \"{}\",\n", format!("{} ", (&game_text.text).into_iter().map(|t| -> String { t.to_string() }).collect::<String>())));
-        },
-        Expr::Audio(game_audio) => { output.push_str(&format!("    \"<{}>\",\n", game_audio.path.to_string())); },
-        Expr::Action(game_action) => { panic!("Discovered Action '{} |{}|' inside of Room '{}'", game_action.action.to_string(), game_action.name.to_string(), state.get_room_name()); },
-        Expr::Require(game_item) => { panic!("Discovered 'Require({})', inside of Room '{}'", game_item.name.to_string(), state.get_room_name()); },
-        Expr::Modify(game_item) => { 
-          //self.inventory.modify(&game_item, &self.current_room_name); 
-          output.push_str(&format!("    \"^{}^\",\n", game_item.name.to_string()));
-        },
-      }
+  pub fn get_sounds(&self) -> &Vec<GameSound> {
+    &self.sounds
+  }
+
+  // Replaces the audio events of the result with their captions as text when captions are on.
+  // Sounds without a caption are left out, there is nothing to show for them.
+  fn caption(&self, result: GameResult) -> GameResult {
+    if !self.captions {
+      return result;
     }
-    output.push_str("  ],\n}");
+    let events = result.events.into_iter().filter_map(|event| match event {
+      GameEvent::Music(cue) | GameEvent::SoundEffect(cue) => cue.caption.map(|caption| GameEvent::plain_text(format!("[{}]", caption))),
+      GameEvent::Stop { .. } => None,
+      event => Some(event),
+    }).collect();
+    GameResult::from_events(events, result.state)
+  }
 
-    output
+  // The JSON of the room variant that the state would enter.
+  pub fn print_room(&self, room_name: &String, state: &GameState) -> Result<String, String> {
+    let (room, _actions) = self.find_room(room_name, state)?;
+    Ok(json::to_json(&room))
   }
 
   pub fn find_room(&self, room_name: &String, state: &GameState) -> Result<(GameRoom, Vec<GameAction>), String> {
-    match self.rooms.get(room_name) {
-      Some((game_rooms, game_actions)) => {
-        for i in 0..game_rooms.len() {
-          if state.inventory.check_items(&game_rooms[i].requirements, &room_name) {
-            return Ok((game_rooms[i].clone(), game_actions.to_vec()));  // TODO handle lifetime so that refrences can be returned.
-          }
-        }
-        return Err(format!("No acceptable room could be found for '{}'", room_name));
-      },
-      None => {
-        println!("Room keys");
-        for key in self.rooms.keys() {
-          println!("{}", key);
-        }
-        return Err(format!("Could not find room '{}'", room_name))
-      },
-    }
+    find_room(&self.rooms, room_name, &state.inventory)
   }
 
   pub fn find_action_index(&self, action_type: &String, action_name: &String, state: &GameState) -> Result<usize, String> {
@@ -542,58 +1029,84 @@ impl Game {
       None => return Err(format!("ICE: Could not find the room '{}'", state.get_room_name())),
     };
 
-    for i in 0..actions.len() {
-      if actions[i].action.to_string().to_uppercase() == action_type.to_string().to_uppercase() && actions[i].name.to_string().to_lowercase() == action_name.to_string().to_lowercase() {
-        if state.inventory.check_items(&actions[i].requirements, &state.get_room_name()) {
-          return Ok(i);
-        }
-      }
+    find_action(actions, state.get_room_name(), action_type.to_string(), action_name.to_string(), &state.inventory)
+  }
+
+  pub fn print_scope(&self, scope: &[ParseNode], state: &GameState) -> GameResult {
+    self.caption(self.run_scope(scope, state, 0))
+  }
+
+  // Moves the player into the first variant of the room whose requirements pass and runs it.
+  fn run_room(&self, room_name: &String, state: &GameState, mut events: Vec<GameEvent>, depth: usize) -> GameResult {
+    if depth > MAX_GOTO_DEPTH {
+      events.push(GameEvent::Error(format!("Error: Gave up entering '{}' after {} gotos in a row", room_name, MAX_GOTO_DEPTH)));
+      return GameResult::from_events(events, state.clone());
     }
-    
-    return Err(format!("Invalid command '{} {}', try again", action_type, action_name));
+
+    let index = match find_room_index(&self.rooms, room_name, &state.inventory) {
+      Ok(i) => i,
+      Err(msg) => {
+        events.push(GameEvent::Error(format!("Error: {}", msg)));
+        return GameResult::from_events(events, state.clone());
+      },
+    };
+    let mut new_state = state.clone();
+    new_state.set_room_name(room_name.to_string());
+    new_state.set_room_index(index);
+    events.push(GameEvent::RoomChange(room_name.to_string()));
+
+    let room = &self.rooms[room_name].0[index];
+    let result = self.run_scope(&room.scope, &new_state, depth);
+    events.extend(result.events);
+    GameResult::from_events(events, result.state)
   }
 
-  pub fn print_scope(&self, scope: &Vec<ParseNode>, state: &GameState) -> GameResult {
-    let mut output = String::new();
+  fn run_scope(&self, scope: &[ParseNode], state: &GameState, depth: usize) -> GameResult {
+    let mut events = Vec::new();
     let mut new_state = state.clone();
     let mut inventory = new_state.get_inventory().clone();
-    for i in 0..scope.len() {
-      match &scope[i].value {
+    for node in scope.iter() {
+      match &node.value {
         Expr::Break => { 
-          output.push_str("|BREAK|\n");
+          events.push(GameEvent::Break);
         },
-        Expr::Delay(token) => { output.push_str(&format!("|{}|\n", token.to_string())); },  // TODO: actually read delay.
+        Expr::NoUndo => { events.push(GameEvent::NoUndo); },
+        Expr::Ending => (),
+        Expr::Delay(token) => { events.push(GameEvent::Delay(delay_millis(token))); },
         Expr::Room(game_room) => { panic!("Discovered Room '{}' inside of Room '{}'", game_room.name.to_string(), new_state.get_room_name()); },
         Expr::Goto(token) => {
-          let new_room_name = token.to_string();
-          match self.find_room(&new_room_name, &state) {
-            Ok((r, _a)) => {
-              new_state.set_room_name(r.name.to_string());
-            },
-            Err(msg) => return GameResult::new(format!("Error: {}", msg), new_state),
-          };
+          // Nothing after a goto is run, the new room takes over from here.
+          new_state.set_inventory(inventory);
+          return self.run_room(&token.to_string(), &new_state, events, depth+1);
         },
-        Expr::Text(game_text) => {
-          if i+1 < scope.len() {
-            match &scope[i+1].value {
-              Expr::Text(_t) => output.push_str(&format!("{} ", (&game_text.text).into_iter().map(|t| -> String { t.to_string() }).collect::<String>())),
-              _ => output.push_str(&format!("{}\n", format!("{} ", (&game_text.text).into_iter().map(|t| -> String { t.to_string() }).collect::<String>()))),
-            }
+        Expr::Text(game_text) => { events.push(GameEvent::from_text(game_text)); },
+        Expr::Audio(game_audio) => {
+          let event = if game_audio.sound_effect {
+            GameEvent::SoundEffect(AudioCue::from_audio(game_audio))
           } else {
-            output.push_str(&format!("{}\n", format!("{} ", (&game_text.text).into_iter().map(|t| -> String { t.to_string() }).collect::<String>())));
-          }
+            GameEvent::Music(AudioCue::from_audio(game_audio))
+          };
+          new_state.play(&event);
+          events.push(event);
+        },
+        Expr::Stop(game_stop) => {
+          let event = GameEvent::Stop { channel: game_stop.channel.clone(), fade: game_stop.fade };
+          new_state.play(&event);
+          events.push(event);
         },
-        Expr::Audio(game_audio) => { output.push_str(&format!("<{}>\n", game_audio.path.to_string())); },
         Expr::Action(game_action) => { panic!("Discovered Action '{} |{}|' inside of Room '{}'", game_action.action.to_string(), game_action.name.to_string(), new_state.get_room_name()); },
+        Expr::Test(game_test) => { panic!("Discovered Test '{}' inside of Room '{}'", game_test.name.to_string(), new_state.get_room_name()); },
+        Expr::Sounds(_game_sounds) => { panic!("Discovered SOUNDS inside of Room '{}'", new_state.get_room_name()); },
         Expr::Require(game_item) => { panic!("Discovered 'Require({})', inside of Room '{}'", game_item.name.to_string(), new_state.get_room_name()); },
         Expr::Modify(game_item) => { 
-          inventory = inventory.modify(&game_item, &new_state.get_room_name()); 
+          inventory = inventory.modify(game_item, new_state.get_room_name()); 
+          events.push(GameEvent::InventoryChange(game_item.clone()));
         },
       }
     }
     new_state.set_inventory(inventory);
 
-    GameResult::new(output, new_state)
+    GameResult::from_events(events, new_state)
   }
 
   pub fn get_current_room(&self, state: &GameState) -> GameRoom {
@@ -605,7 +1118,7 @@ impl Game {
     let mut inventory_change = false;
     let mut new_state = state.clone();
     let mut inventory = new_state.get_inventory().clone();
-    let room = match self.find_room(&state.get_room_name(), &state) {
+    let room = match self.find_room(state.get_room_name(), state) {
       Ok((r, _a)) => r,
       Err(msg) => panic!("{}", msg),
     };
@@ -614,7 +1127,7 @@ impl Game {
         Expr::Room(game_room) => { panic!("Discovered Room '{}' inside of Room '{}'", game_room.name.to_string(), new_state.get_room_name()); },
         Expr::Goto(token) => {
           let new_room_name = token.to_string();
-          match self.find_room(&new_room_name, &state) {
+          match self.find_room(&new_room_name, state) {
             Ok((r, _a)) => {
               new_state.set_room_name(r.name.to_string());
               room_change = true;
@@ -623,9 +1136,11 @@ impl Game {
           };
         },
         Expr::Action(game_action) => { panic!("Discovered Action '{} |{}|' inside of Room '{}'", game_action.action.to_string(), game_action.name.to_string(), new_state.get_room_name()); },
+        Expr::Test(game_test) => { panic!("Discovered Test '{}' inside of Room '{}'", game_test.name.to_string(), new_state.get_room_name()); },
+        Expr::Sounds(_game_sounds) => { panic!("Discovered SOUNDS inside of Room '{}'", new_state.get_room_name()); },
         Expr::Require(game_item) => { panic!("Discovered 'Require({})', inside of Room '{}'", game_item.name.to_string(), new_state.get_room_name()); },
         Expr::Modify(game_item) => { 
-          inventory = inventory.modify(&game_item, &new_state.get_room_name()); 
+          inventory = inventory.modify(game_item, new_state.get_room_name()); 
           inventory_change = true;
         },
         _ => (),
@@ -639,20 +1154,19 @@ impl Game {
 #[wasm_bindgen]
 impl Game {
   pub fn start(&self) -> GameResult {
-    let state = GameState::init();
+    let mut state = GameState::init();
     let rooms = match self.rooms.get(&state.current_room_name) {
        Some((r, _a)) => r,
-       None => return GameResult::new(format!("Error: ROOM |{}| not found", state.get_room_name()), state),
+       None => return GameResult::error(format!("Error: ROOM |{}| not found", state.get_room_name()), state),
      };
 
-    for i in 0..rooms.len() {
-      if state.inventory.check_items(&rooms[i].requirements, &state.get_room_name()) {
-        return self.print_scope(&rooms[i].scope, &state);
-      }
+    if let Ok(i) = find_room_index(&self.rooms, &state.current_room_name, &state.inventory) {
+      state.set_room_index(i);
+      return self.print_scope(&rooms[i].scope, &state);
     }
 
-    let text = format!("Error: Could not find any ROOM |{}| for which satisfied the current inventory requirements\n{}", state.current_room_name, state.inventory.to_string(&state.get_room_name()));
-    GameResult::new(text, state)
+    let text = format!("Error: Could not find any ROOM |{}| for which satisfied the current inventory requirements\n{}", state.current_room_name, state.inventory.to_string(state.get_room_name()));
+    GameResult::error(text, state)
   }
 
   pub fn list_all_rooms(&self, state: &GameState) -> GameResult {
@@ -661,26 +1175,86 @@ impl Game {
   }
 
   pub fn print_current_room(&self, state: &GameState) -> GameResult {
-    self.print_scope(&self.get_current_room(&state).scope, &state)
+    self.print_scope(&self.get_current_room(state).scope, state)
+  }
+
+  // Shows the room the state is in, but keeps the state as it is rather than the one the room
+  // would leave behind, with audio events that play the music of the state instead of the ones in
+  // the room. For when nothing is playing, ie after a page refresh.
+  pub fn resume(&self, state: &GameState) -> GameResult {
+    self.resume_from(&GameState::init(), state)
+  }
+
+  // Like resume, but with the music of the playing state already playing, ie the state before an
+  // undo. Channels that the state does not play are stopped and music that carries on is left
+  // alone, so it does not start over.
+  pub fn resume_from(&self, playing: &GameState, state: &GameState) -> GameResult {
+    let mut events = Vec::new();
+    for channel in playing.audio.keys().filter(|c| !state.audio.contains_key(*c)) {
+      events.push(GameEvent::Stop { channel: channel.clone(), fade: 0 });
+    }
+    for (channel, cue) in state.audio.iter() {
+      if playing.audio.get(channel) != Some(cue) {
+        events.push(GameEvent::Music(cue.clone()));
+      }
+    }
+    // Only the text of the room is shown again. Running its scope would repeat its breaks and
+    // delays and follow its gotos, and its modifications are already in the state.
+    for node in self.get_current_room(state).scope.iter() {
+      if let Expr::Text(game_text) = &node.value {
+        events.push(GameEvent::from_text(game_text));
+      }
+    }
+    self.caption(GameResult::from_events(events, state.clone()))
+  }
+
+  // Turns on the text only mode, where every sound is shown as its caption in brackets instead of
+  // being played, ie '[A door slams shut]'.
+  pub fn set_captions(&mut self, captions: bool) {
+    self.captions = captions;
   }
 
   pub fn query(&self, action: String, command: String, state: &GameState) -> GameResult {
-    let index = match self.find_action_index(&action, &command, &state) {
+    let index = match self.find_action_index(&action, &command, state) {
       Ok(i) => i,
-      Err(msg) => return GameResult::new(format!("Could not find action: {} |{}| under ROOM |{}|. ({})", action, command, state.get_room_name(), msg), state.clone()),
+      Err(msg) => return GameResult::error(format!("Could not find action: {} |{}| under ROOM |{}|. ({})", action, command, state.get_room_name(), msg), state.clone()),
     };
 
     let scope = &self.rooms.get(state.get_room_name()).unwrap().1[index].scope;
 
-    self.print_scope(&scope, &state)
+    self.print_scope(scope, state)
+  }
+
+  // Runs a line typed by the player, ie 'examine glass', in the same way as the web shell.
+  pub fn command(&self, line: String, state: &GameState) -> GameResult {
+    let (action, argument) = parse_command(&line);
+    if action == "INVENTORY" {
+      return self.print_inventory(state);
+    }
+    self.query(action, argument, state)
   }
 
   pub fn print_inventory(&self, state: &GameState) -> GameResult {
-    let text = state.inventory.to_string(&state.get_room_name()).to_string();
+    let text = state.inventory.to_string(state.get_room_name()).to_string();
     GameResult::new(text, state.clone())
   }
 }
 
+// Splits a line of player input into an action and its argument. A lone word is treated as a
+// MISC action, except for 'help' and 'inventory' which have their own sections.
+fn parse_command(line: &str) -> (String, String) {
+  let words: Vec<&str> = line.split_whitespace().collect();
+  match words.len() {
+    0 => ("MISC".to_string(), String::new()),
+    1 => match words[0].to_lowercase().as_str() {
+      "help" => ("HELP".to_string(), "help".to_string()),
+      "inventory" => ("INVENTORY".to_string(), String::new()),
+      word => ("MISC".to_string(), word.to_string()),
+    },
+    _ => (words[0].to_uppercase(), words[1].to_lowercase()),
+  }
+}
+
 
 // Basic contructor for Token
 impl Token {
@@ -691,6 +1265,7 @@ impl Token {
     }
   }
 
+  #[allow(clippy::inherent_to_string)]
   pub fn to_string(&self) -> String {
     token_kind_to_string(&self.kind)
   }
@@ -717,7 +1292,7 @@ impl Program {
 
     Program {
       filename: name,
-      text: text,
+      text,
       tokens: Vec::new(),
       row_index: index,
     }
@@ -725,7 +1300,6 @@ impl Program {
 
   pub fn get_location(&self, index: usize) -> (usize, usize) {
     assert!(index < self.text.len(), "Attempted to index outside the bounds of the program text");
-    print!("Finding location: \n");
     let mut last_pos = 0;
     for (i, pos) in self.row_index.iter().enumerate() {
       if *pos > index {
@@ -812,11 +1386,10 @@ impl Program {
   }
 
   #[allow(dead_code)]
-  pub fn print_tokens(&self) {
-    for token in &self.tokens {
-      let string = token.to_string();
-      print!("{} ", string);
-    }
+  pub fn print_tokens(&self, out: &mut dyn Write) {
+    let text: String = self.tokens.iter().map(|token| format!("{} ", token.to_string())).collect();
+    // Errors are ignored so that piping into ie 'head' does not panic once it stops reading.
+    let _ = out.write_all(text.as_bytes());
   }
 
   #[allow(dead_code)]
@@ -875,10 +1448,7 @@ impl Program {
 
   pub fn get_scope(&self, pos: usize, opening: TokenKind) -> Result<(Vec<Token>, usize), String> {
     let mut scope = Vec::new();
-    let start_pos = match self.eat_token(pos, &opening) {
-      Ok(i) => i,
-      Err(msg) => return Err(msg),
-    };
+    let start_pos = self.eat_token(pos, &opening)?;
     let end_pos = match self.get_token(start_pos, &closing_token(&opening)) {
       Ok((_tok, i)) => i,
       Err(msg) => return Err(msg),
@@ -891,11 +1461,7 @@ impl Program {
 
   #[allow(dead_code)]
   pub fn peek_token(&self, pos: usize, token: TokenKind) -> bool {
-    if pos+1 < self.tokens.len() && match_token_kind(&token, &self.tokens[pos+1].kind) {
-      true
-    } else {
-      false
-    }
+    pos+1 < self.tokens.len() && match_token_kind(&token, &self.tokens[pos+1].kind)
   }
 
   // Formats a string in the form of an error.
@@ -940,227 +1506,197 @@ impl Program {
   }
 }
 
+// Runs the terminal binary with the arguments it was given, see cli.rs.
 pub fn run() {
-  let root_path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-  let www_path = root_path.join("www");
-  let narrative_path = www_path.join("narrative.txt");
-  let mut program = read_program(&narrative_path);
-  match lex(&program) {
-    Ok(tok) => program.tokens = tok,
-    Err(msg) => panic!("Error: {}\n", msg),
-  }
-
-  //program.print_tokens();
-  let nodes = match parse(&program) {
-    Ok(nodes) => nodes,
-    Err(msg) => panic!("Error: {}\n", msg),
-  };
-
-  let rooms = match setup_rooms(&nodes) {
-    Ok(rooms) => rooms,
-    Err(msg) => panic!("Error: {}\n", msg),
-  };
-
-  match start_game(&rooms) {
-    Ok(_) => (),
-    Err(msg) => panic!("Error: {}\n", msg),
-  }
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  std::process::exit(cli::main(&args));
 }
 
 #[wasm_bindgen]
 #[allow(dead_code)]
 pub fn compile(text: String) -> Game {
   let mut program = read_program_from_string(&text);
-  match lex(&program) {
-    Ok(tok) => program.tokens = tok,
+  match build_game(&mut program) {
+    Ok(game) => game,
     Err(msg) => panic!("Error: {}\n", msg),
   }
+}
 
+// Compiles the script and makes sure that every sound it plays is a file under the asset root,
+// ie 'www' for the web build. The browser cannot look at the files, so compile does not check.
+pub fn compile_with_assets(text: String, asset_root: &Path) -> Result<Game, String> {
+  let mut program = read_program_from_string(&text);
+  let game = build_game(&mut program)?;
+  let missing = check::check_assets(&game, asset_root);
+  if !missing.is_empty() {
+    let errors: Vec<String> = missing.iter().map(|d| d.render(&program)).collect();
+    return Err(errors.join("\n\n"));
+  }
+  Ok(game)
+}
 
+// Lexes and parses the program and builds the game out of it.
+fn build_game(program: &mut Program) -> Result<Game, String> {
+  program.tokens = lex(program)?;
   //program.print_tokens();
-  let nodes = match parse(&program) {
-    Ok(nodes) => nodes,
-    Err(msg) => panic!("Error: {}\n", msg),
-  };
+  let mut nodes = parse(program)?;
+  let sounds = setup_sounds(program, &mut nodes)?;
+  let rooms = setup_rooms(&nodes)?;
+  Ok(Game::new(&rooms, &setup_tests(&nodes), &sounds))
+}
 
-  let rooms = match setup_rooms(&nodes) {
-    Ok(rooms) => rooms,
-    Err(msg) => panic!("Error: {}\n", msg),
-  };
+fn find_room(rooms: &RoomMap, room_name: &String, inventory: &Inventory) -> Result<(GameRoom, Vec<GameAction>), String> {
+  let index = find_room_index(rooms, room_name, inventory)?;
+  let (game_rooms, game_actions) = &rooms[room_name];
+  Ok((game_rooms[index].clone(), game_actions.to_vec()))  // TODO handle lifetime so that refrences can be returned.
+}
+
+// How strongly a section is preferred over the others that could run: the highest '|PRIORITY n|'
+// first and then the one with the most requirements, like the specificity of a CSS selector.
+pub fn section_rank(requirements: &[GameItem], priority: u32) -> (u32, usize) {
+  (priority, requirements.len())
+}
 
-  let game = Game::new(&rooms);
-  game
+// Picks the section to run out of those whose requirements pass, by section_rank and then by the
+// order of the script. The sections are their requirements and priority.
+fn choose_section<'a>(sections: impl Iterator<Item = (usize, &'a Vec<GameItem>, u32)>, room_name: &String, inventory: &Inventory) -> Option<usize> {
+  let mut chosen: Option<(usize, (u32, usize))> = None;
+  for (i, requirements, priority) in sections {
+    let rank = section_rank(requirements, priority);
+    let better = match chosen {
+      Some((_i, best)) => rank > best,
+      None => true,
+    };
+    if better && inventory.check_items(requirements, room_name) {
+      chosen = Some((i, rank));
+    }
+  }
+  chosen.map(|(i, _rank)| i)
 }
 
-fn find_room(rooms: &HashMap<String, (Vec<GameRoom>, Vec<GameAction>)>, room_name: &String, inventory: &Inventory) -> Result<(GameRoom, Vec<GameAction>), String> {
+// Finds which variant of the room to enter, see choose_section.
+fn find_room_index(rooms: &RoomMap, room_name: &String, inventory: &Inventory) -> Result<usize, String> {
   match rooms.get(room_name) {
-    Some((game_rooms, game_actions)) => {
-      for i in 0..game_rooms.len() {
-        if inventory.check_items(&game_rooms[i].requirements, &room_name) {
-          return Ok((game_rooms[i].clone(), game_actions.to_vec()));  // TODO handle lifetime so that refrences can be returned.
-        }
-      }
-      return Err(format!("No acceptable room could be found for '{}'", room_name));
-    },
-    None => {
-      println!("Room keys");
-      for key in rooms.keys() {
-        println!("{}", key);
+    Some((game_rooms, _game_actions)) => {
+      let variants = game_rooms.iter().enumerate().map(|(i, r)| (i, &r.requirements, r.priority));
+      match choose_section(variants, room_name, inventory) {
+        Some(i) => Ok(i),
+        None => Err(format!("No acceptable room could be found for '{}'", room_name)),
       }
-      return Err(format!("Could not find room '{}'", room_name))
     },
+    None => Err(format!("Could not find room '{}'", room_name)),
   }
 }
 
-fn find_action(actions: &Vec<GameAction>, room_name: &String, action_type: String, action_name: String, inventory: &Inventory) -> Result<usize, String> {
-  for i in 0..actions.len() {
-    if actions[i].action.to_string().to_uppercase() == action_type.to_string().to_uppercase() && actions[i].name.to_string().to_lowercase() == action_name.to_string().to_lowercase() {
-      if inventory.check_items(&actions[i].requirements, &room_name) {
-        return Ok(i);
-      }
+fn find_action(actions: &[GameAction], room_name: &String, action_type: String, action_name: String, inventory: &Inventory) -> Result<usize, String> {
+  let matching = actions.iter().enumerate()
+    .filter(|(_i, a)| a.action.to_string().to_uppercase() == action_type.to_uppercase() && a.name.to_string().to_lowercase() == action_name.to_lowercase())
+    .map(|(i, a)| (i, &a.requirements, a.priority));
+  match choose_section(matching, room_name, inventory) {
+    Some(i) => Ok(i),
+    None => Err(format!("Invalid command '{} {}', try again", action_type, action_name)),
+  }
+}
+
+// Prints the events of a turn to the terminal, waiting for the player at every break.
+fn print_events(events: &[GameEvent], screen: &mut terminal::Screen) -> Result<(), String> {
+  for event in events {
+    match event {
+      GameEvent::Text { text, italic, bold, color, .. } => screen.text(text, *italic, *bold, *color)?,
+      GameEvent::Break => screen.wait()?,
+      GameEvent::Delay(ms) => {
+        if let Err(msg) = std::io::stdout().flush() {
+          return Err(msg.to_string());
+        }
+        thread::sleep(time::Duration::from_millis(u64::from(*ms)));
+      },
+      // There is no audio in the terminal, so just note what would be playing.
+      GameEvent::Music(cue) => screen.paragraph(&format!("[music: {}]", cue.caption.as_deref().unwrap_or(audio_name(&cue.path))))?,
+      GameEvent::SoundEffect(cue) => screen.paragraph(&format!("[sound: {}]", cue.caption.as_deref().unwrap_or(audio_name(&cue.path))))?,
+      GameEvent::Stop { channel, .. } => screen.paragraph(&format!("[{} stops]", channel))?,
+      GameEvent::RoomChange(_) | GameEvent::InventoryChange(_) | GameEvent::NoUndo => (),
+      GameEvent::Error(msg) => screen.paragraph(msg)?,
     }
   }
-  
-  return Err(format!("Invalid command '{} {}', try again", action_type, action_name));
+  screen.end_turn();
+  Ok(())
 }
 
-fn start_game(rooms: &HashMap<String, (Vec<GameRoom>, Vec<GameAction>)>) -> Result<bool, String> {
-  let mut inventory = Inventory::new();
-  let current_room = String::from("init");
-  let (mut room, mut actions) = match find_room(&rooms, &current_room, &inventory) {
-    Ok((room, actions)) => (room, actions),
-    Err(msg) => return Err(msg),
+// The file name of an audio path without its directory or extension, ie 'glassFilled'.
+fn audio_name(path: &str) -> &str {
+  match Path::new(path).file_stem().and_then(|stem| stem.to_str()) {
+    Some(name) => name,
+    None => path,
+  }
+}
+
+fn start_game(game: &Game, mut recorder: Option<transcript::Recorder>) -> Result<bool, String> {
+  let mut session = session::Session::new(game, history::TurnHistory::default());
+  let mut screen = terminal::Screen::detect();
+  let mut record = |command: Option<&str>, result: &GameResult| -> Result<(), String> {
+    match recorder.as_mut() {
+      Some(r) => r.record(command, result),
+      None => Ok(()),
+    }
   };
+  record(None, session.get_result())?;
+  print_events(session.get_result().get_events(), &mut screen)?;
 
   let mut line = String::new();
   loop {
-    println!("Now in Room: {}", room.name.to_string());
-    let mut skip_actions = false;
-    for i in 0..room.scope.len() {
-      match &room.scope[i].value {
-        Expr::Break => { 
-          print!("\n");
-          match std::io::stdin().read_line(&mut line) {
-            Ok(_) => (),
-            Err(msg) => return Err(msg.to_string()),
-          }
-          line.clear();
-        },
-        Expr::Delay(_token) => { thread::sleep(time::Duration::from_secs(5)); },  // TODO: actually read delay.
-        Expr::Room(game_room) => { return Err(format!("Discovered Room '{}' inside of Room '{}'", game_room.name.to_string(), current_room)); },
-        Expr::Goto(token) => {
-          let new_room_name = token.to_string();
-          match find_room(&rooms, &new_room_name, &inventory) {
-            Ok((r, a)) => {
-              room = r;
-              actions = a;
-            },
-            Err(msg) => return Err(msg),
-          };
-          skip_actions = true;
-          break;
-        },
-        Expr::Text(game_text) => {
-          print!("{} ", (&game_text.text).into_iter().map(|t| -> String { t.to_string() }).collect::<String>());
-        },
-        Expr::Audio(_game_audio) => { continue; },
-        Expr::Action(game_action) => { return Err(format!("Discovered Action '{} |{}|' inside of Room '{}'", game_action.action.to_string(), game_action.name.to_string(), current_room)); },
-        Expr::Require(game_item) => { return Err(format!("Discovered 'Require({})', inside of Room '{}'", game_item.name.to_string(), current_room)); },
-        Expr::Modify(game_item) => { inventory = inventory.modify(&game_item, &current_room); },
-      }
+    println!();
+    if let Err(msg) = std::io::stdout().flush() {
+      return Err(msg.to_string());
     }
-
-    if skip_actions {
-      continue;
+    line.clear();
+    match std::io::stdin().read_line(&mut line) {
+      Ok(0) => return Ok(true),
+      Ok(_) => (),
+      Err(msg) => return Err(msg.to_string()),
     }
 
-
-    let mut leave_actions = false;
-    while !leave_actions {
-
-      print!("\n");
-      let index: usize;
-      loop {
-        let mut command: String = "MISC".to_string();
-        let argument: String;
-        loop {
-          line.clear();
-          std::io::stdin().read_line(&mut line).expect("Failed to read input");
-          line = format!("{}", line.replace('\n', ""));
-          line = format!("{}", line.replace('\r', ""));
-          let split: Vec<&str> = line.split(" ").collect();
-          if split.len() == 0 {
-            continue;
-          } else if split.len() == 1 {
-            argument = split[0].to_lowercase();
-            break;
-          } else {
-            command = split[0].to_uppercase();
-            argument = split[1].to_lowercase();
-            break;
-          }
-        }
-
-        match find_action(&actions, &current_room, command.clone(), argument.clone(), &inventory) {
-          Ok(i) => {
-            index = i;
-            break;
-          },
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+      ["quit"] => return Ok(true),
+      ["save"] | ["save", _] => {
+        let path = words.get(1).copied().unwrap_or(save::DEFAULT_SAVE_PATH);
+        match save::save_to_file(game, session.get_state(), path) {
+          Ok(_) => println!("Saved to {}", path),
           Err(msg) => println!("{}", msg),
         }
-      }
-      let action = &actions[index];
-
-      for i in 0..action.scope.len() {
-        match &action.scope[i].value {
-          Expr::Break => { 
-            print!("\n");
-            match std::io::stdin().read_line(&mut line) {
-              Ok(_) => (),
-              Err(msg) => return Err(msg.to_string()),
-            }
-            line.clear();
-          },
-          Expr::Delay(_token) => { thread::sleep(time::Duration::from_secs(5)); },  // TODO: actually read delay.
-          Expr::Room(game_room) => { return Err(format!("Discovered Room '{}' inside of Action '{} |{}|'", game_room.name.to_string(), action.action.to_string(), action.name.to_string())); },
-          Expr::Goto(token) => {
-            let new_room_name = token.to_string();
-            match find_room(&rooms, &new_room_name, &inventory) {
-              Ok((r, a)) => {
-                room = r;
-                actions = a;
-              },
-              Err(msg) => return Err(msg),
-            };
-            leave_actions = true;
-            break;
-          },
-          Expr::Text(game_text) => {
-            print!("{} ", (&game_text.text).into_iter().map(|t| -> String { t.to_string() }).collect::<String>());
+      },
+      ["load"] | ["load", _] => {
+        let path = words.get(1).copied().unwrap_or(save::DEFAULT_SAVE_PATH);
+        match save::load_from_file(game, path) {
+          Ok(state) => {
+            println!("Loaded {}", path);
+            let result = session.restore(state);
+            record(Some(&line), result)?;
+            print_events(result.get_events(), &mut screen)?;
           },
-          Expr::Audio(_game_audio) => { continue; },
-          Expr::Action(game_action) => { return Err(format!("Discovered Action '{} |{}|' inside of Action '{} |{}|'", game_action.action.to_string(), game_action.name.to_string(), action.action.to_string(), action.name.to_string())); },
-          Expr::Require(game_item) => { return Err(format!("Discovered 'Require({})', inside of Action '{} |{}|'", game_item.name.to_string(), action.action.to_string(), action.name.to_string())); },
-          Expr::Modify(game_item) => { inventory.modify(&game_item, &current_room); },
+          Err(msg) => println!("{}", msg),
         }
-      }
+      },
+      _ => {
+        let result = session.input(&line);
+        record(Some(&line), result)?;
+        print_events(result.get_events(), &mut screen)?;
+      },
     }
   }
 }
 
-fn setup_rooms(nodes: &Vec<ParseNode>) -> Result<HashMap<String, (Vec<GameRoom>, Vec<GameAction>)>, String> {
-  let mut rooms = HashMap::<String, (Vec<GameRoom>, Vec<GameAction>)>::new();
+fn setup_rooms(nodes: &[ParseNode]) -> Result<RoomMap, String> {
+  let mut rooms = RoomMap::new();
   let mut grouped_rooms = Vec::<GameRoom>::new();
   let mut grouped_actions = Vec::<GameAction>::new();
   let mut current_room_name: String = "".to_string();
-  for i in 0..nodes.len() {
-    match &nodes[i].value {
+  for node in nodes.iter() {
+    match &node.value {
       Expr::Room(game_room) => {
         if game_room.name.to_string() != current_room_name {
-          if !grouped_rooms.is_empty() {
-            if rooms.contains_key(&current_room_name) {
-              return Err(format!("The room name '{}' was found in multiple differnt sections", current_room_name));
-            }
+          if !grouped_rooms.is_empty() && rooms.contains_key(&current_room_name) {
+            return Err(format!("The room name '{}' was found in multiple differnt sections", current_room_name));
           }
 
           rooms.insert(current_room_name.clone(), (grouped_rooms.clone(), grouped_actions.clone()));
@@ -1173,7 +1709,11 @@ fn setup_rooms(nodes: &Vec<ParseNode>) -> Result<HashMap<String, (Vec<GameRoom>,
       Expr::Action(game_action) => {
         grouped_actions.push(game_action.clone());
       },
-      other => return Err(format!("Found '{}' on the top level tree nodes", expr_to_string(&other))),
+      // Tests are only run by the test command, see setup_tests.
+      Expr::Test(_game_test) => (),
+      // The sounds are looked up before the rooms are set up, see setup_sounds.
+      Expr::Sounds(_game_sounds) => (),
+      other => return Err(format!("Found '{}' on the top level tree nodes", expr_to_string(other))),
     }
   }
 
@@ -1187,23 +1727,66 @@ fn setup_rooms(nodes: &Vec<ParseNode>) -> Result<HashMap<String, (Vec<GameRoom>,
   Ok(rooms)
 }
 
-// Creates a program by reading in a file from the given path
-fn read_program(filename: &Path) -> Program {
-  let display = filename.display();
-  let mut file = match File::open(&filename) {
-    Ok(f) => f,
-    Err(msg) => panic!("Could not open {}: {}", display, msg),
-  };
+fn setup_tests(nodes: &[ParseNode]) -> Vec<GameTest> {
+  nodes.iter().filter_map(|node| match &node.value {
+    Expr::Test(game_test) => Some(game_test.clone()),
+    _ => None,
+  }).collect()
+}
 
-  let mut text_string = String::new();
-  match file.read_to_string(&mut text_string) {
-    Ok(_) => (),
-    Err(msg) => panic!("Could not read {}: {}", display, msg),
-  };
+// Replaces every sound id played in a room or action with the path and options of its entry in
+// the SOUNDS sections. Anything with a '/' or '.' that is not an id is taken to be a path.
+fn setup_sounds(program: &Program, nodes: &mut [ParseNode]) -> Result<Vec<GameSound>, String> {
+  let mut sounds: Vec<GameSound> = Vec::new();
+  for node in nodes.iter() {
+    if let Expr::Sounds(game_sounds) = &node.value {
+      for game_sound in game_sounds.iter() {
+        if sounds.iter().any(|s| s.id.to_string() == game_sound.id.to_string()) {
+          return Err(token_error(program, &format!("The sound '{}' is declared more than once", game_sound.id.to_string()), &game_sound.id));
+        }
+        sounds.push(game_sound.clone());
+      }
+    }
+  }
 
-  let text = text_string.chars().collect();
-  println!("DISPLAY: {}", display.to_string());
-  Program::new(display.to_string(), text)
+  let ids: Vec<String> = sounds.iter().map(|s| s.id.to_string()).collect();
+  for node in nodes.iter_mut() {
+    let scope = match &mut node.value {
+      Expr::Room(game_room) => &mut game_room.scope,
+      Expr::Action(game_action) => &mut game_action.scope,
+      _ => continue,
+    };
+    for scope_node in scope.iter_mut() {
+      let game_audio = match &mut scope_node.value {
+        Expr::Audio(game_audio) => game_audio,
+        _ => continue,
+      };
+      let name = game_audio.path.to_string();
+      match sounds.iter().find(|s| s.id.to_string() == name) {
+        Some(game_sound) => {
+          let id = game_audio.path.clone();
+          *game_audio = GameAudio::new(game_sound.path.clone(), game_audio.sound_effect, Some(id), game_audio.options.clone(), &game_sound.options);
+        },
+        None if name.contains('/') || name.contains('.') => (),
+        None => {
+          let error_type = match check::closest(&name, &ids.iter().collect::<Vec<&String>>()) {
+            Some(id) => format!("Unknown sound '{}', did you mean '{}'?", name, id),
+            None => format!("Unknown sound '{}', add it to a SOUNDS section or give the path to the file", name),
+          };
+          return Err(token_error(program, &error_type, &game_audio.path));
+        },
+      }
+    }
+  }
+  Ok(sounds)
+}
+
+// Creates a program by reading in a file from the given path
+fn read_program(filename: &Path) -> Result<Program, String> {
+  match std::fs::read_to_string(filename) {
+    Ok(text) => Ok(Program::new(filename.display().to_string(), text.chars().collect())),
+    Err(msg) => Err(format!("Could not read {}: {}", filename.display(), msg)),
+  }
 }
 
 #[allow(dead_code)]
@@ -1215,60 +1798,57 @@ fn read_program_from_string(text: &String) -> Program {
 
 // Tests whether or not a character is considered to be a "text" character
 fn is_text(ch: char) -> bool {
-  match ch {
-    'a'..='z' => true,
-    'A'..='Z' => true,
-    '0'..='9' => true,
-    '"' => true,
-    '\'' => true,
-    ',' => true,
-    '.' => true,
-    '-' => true,
-    '_' => true,
-    ':' => true,
-    ';' => true,
-    '!' => true,
-    '?' => true,
-    '/' => true,
-    '#' => true,
-    '@' => true,
-    '(' => true,
-    ')' => true,
-    '~' => true,
-    _ => false,
-  }
+  matches!(ch,
+    'a'..='z'
+      | 'A'..='Z'
+      | '0'..='9'
+      | '"'
+      | '\''
+      | ','
+      | '.'
+      | '-'
+      | '_'
+      | ':'
+      | ';'
+      | '!'
+      | '?'
+      | '/'
+      | '@'
+      | '('
+      | ')'
+      | '~'
+  )
 }
 
 fn is_whitespace(ch: char) -> bool {
-  match ch {
-    ' ' => true,
-    '\t' => true,
-    '\n' => true,
-    '\r' => true,
-    _ => false,
-  }
+  matches!(ch,
+    ' '
+      | '\t'
+      | '\n'
+      | '\r'
+  )
 }
 
 fn is_whitespace_token(kind: &TokenKind) -> bool {
-  match kind {
-    TokenKind::Newline => true,
-    _ => false,
-  }
+  matches!(kind, TokenKind::Newline)
 }
 
 fn is_keyword(text: &str) -> bool {
-  match text {
-    "ROOM" => true,
-    "BREAK" => true,
-    "HELP" => true,
-    "MISC" => true,
-    "EXAMINE" => true,
-    "USE" => true,
-    "TAKE" => true,
-    "TALK" => true,
-    "GO" => true,
-    _ => false,
-  }
+  matches!(text,
+    "ROOM"
+      | "BREAK"
+      | "NOUNDO"
+      | "ENDING"
+      | "HELP"
+      | "MISC"
+      | "EXAMINE"
+      | "USE"
+      | "TAKE"
+      | "TALK"
+      | "GO"
+      | "TEST"
+      | "SOUNDS"
+  )
 }
 
 fn closing_token(kind: &TokenKind) -> TokenKind {
@@ -1310,7 +1890,7 @@ fn lex(program: &Program) -> Result<Vec<Token>, String> {
     len = 1;
     
     match ch {
-      //'#' => tokens.push(Token::new(TokenKind::Pound, index)),
+      '#' => tokens.push(Token::new(TokenKind::Pound, index)),
       '%' => tokens.push(Token::new(TokenKind::Percent, index)),
       '&' => tokens.push(Token::new(TokenKind::Ampersand, index)),
       //'(' => tokens.push(Token::new(TokenKind::OpenParen, index)),
@@ -1344,13 +1924,10 @@ fn lex(program: &Program) -> Result<Vec<Token>, String> {
 
       ch if is_text(ch) => {
         let mut text = program.read_while(|ch| {is_text(ch) || ch == ' '}, index);
-        match text.pop() {
-          Some(ch) => {
-            if !is_whitespace(ch) {
-              text.push(ch)
-            }
-          },
-          None => (),
+        if let Some(ch) = text.pop() {
+          if !is_whitespace(ch) {
+            text.push(ch)
+          }
         }
         len = text.len();
         if is_keyword(&text) {
@@ -1410,10 +1987,8 @@ fn lex(program: &Program) -> Result<Vec<Token>, String> {
 fn parse_token(program: &Program, pos: usize) -> Result<(ParseNode, usize), String> {
   program.eat_whitespace_tokens(pos).and_then(|i| match &program.tokens[i].kind {
     TokenKind::Text(_t) => {
-      let mut text = Vec::new();
-      text.push(program.tokens[i].clone());
       let game_text = GameText {
-        text: text,
+        text: vec![program.tokens[i].clone()],
         itallic: false,
         bold: false,
         color: 0,
@@ -1455,8 +2030,8 @@ fn parse_token(program: &Program, pos: usize) -> Result<(ParseNode, usize), Stri
           let action = InventoryAction::Check;
           let item = GameItem {
             name: var,
-            action: action,
-            inventory: inventory,
+            action,
+            inventory,
           };
           Ok((ParseNode::new(Expr::Require(item)), index))
         },
@@ -1471,22 +2046,13 @@ fn parse_token(program: &Program, pos: usize) -> Result<(ParseNode, usize), Stri
             let error_type = String::from("Sound effect must be in the format '*path/to/audio*'");
             return Err(program.get_error_msg(&error_type, program.tokens[i].index, program.tokens[index-1].index+1));
           }
-          if let TokenKind::Text(t) = &tokens[0].kind {
-            let path = tokens[0].clone();
-            let music = GameAudio {
-              path: path,
-              sound_effect: true,
-            };
-            // Make sure the path exists at compile time.
-            let relative_path = format!("www/{}", t);
-            if !path_exists(&relative_path) {
-              let error_type = String::from("Sound effect does not exist");
-              return Err(program.get_error_msg(&error_type, program.tokens[i].index, program.tokens[index-1].index+1));
-            }
-            return Ok((ParseNode::new(Expr::Audio(music)), index))
+          if let TokenKind::Text(_t) = &tokens[0].kind {
+            let music = parse_audio(program, &tokens[0], true)?;
+            // Whether the file exists is checked against an asset root, see compile_with_assets.
+            Ok((ParseNode::new(Expr::Audio(music)), index))
           } else {
             let error_type = String::from("Incorrect type in play music action, must be in the format '*path/to/audio*'");
-            return Err(program.get_error_msg(&error_type, program.tokens[i].index, program.tokens[index-1].index+1));
+            Err(program.get_error_msg(&error_type, program.tokens[i].index, program.tokens[index-1].index+1))
           }
         },
         Err(msg) => Err(msg),
@@ -1496,7 +2062,7 @@ fn parse_token(program: &Program, pos: usize) -> Result<(ParseNode, usize), Stri
     TokenKind::At => {
       match program.get_scope(i, TokenKind::At) {
         Ok((tokens, index)) => {
-          if tokens.len() == 0 {
+          if tokens.is_empty() {
             let error_type = String::from("Colored text must be in the format '@xxxxxx ..@' where xxxxxx is a 24 bit hex number of the color");
             return Err(program.get_error_msg(&error_type, program.tokens[i].index, program.tokens[index-1].index+1));
           } 
@@ -1506,7 +2072,7 @@ fn parse_token(program: &Program, pos: usize) -> Result<(ParseNode, usize), Stri
           for tok in text_tokens.iter() {
             match &tok.kind {
               TokenKind::Text(_t) => (),
-              other => return Err(format!("Colored text '@xxxxxx ..@' only supports string objects however {} was found", token_kind_to_string(&other))),
+              other => return Err(format!("Colored text '@xxxxxx ..@' only supports string objects however {} was found", token_kind_to_string(other))),
             }
           }
           let game_text = GameText {
@@ -1553,7 +2119,7 @@ fn parse_token(program: &Program, pos: usize) -> Result<(ParseNode, usize), Stri
                   return Err("Cannot have more than three '+'s in inventory modification".to_string())
                 }
               },
-              other => return Err(format!("Unexpected {} in inventory modification '^..^'", token_kind_to_string(&other))),
+              other => return Err(format!("Unexpected {} in inventory modification '^..^'", token_kind_to_string(other))),
             }
           }
           let inventory = match pos {
@@ -1566,8 +2132,8 @@ fn parse_token(program: &Program, pos: usize) -> Result<(ParseNode, usize), Stri
           };
           let item = GameItem {
             name: var,
-            action: action,
-            inventory: inventory,
+            action,
+            inventory,
           };
           Ok((ParseNode::new(Expr::Modify(item)), index))
         },
@@ -1592,8 +2158,8 @@ fn parse_token(program: &Program, pos: usize) -> Result<(ParseNode, usize), Stri
           let action = InventoryAction::Check;
           let item = GameItem {
             name: var,
-            action: action,
-            inventory: inventory,
+            action,
+            inventory,
           };
           Ok((ParseNode::new(Expr::Require(item)), index))
         },
@@ -1607,16 +2173,10 @@ fn parse_token(program: &Program, pos: usize) -> Result<(ParseNode, usize), Stri
             return Err(format!("Music must be in the format '<path/to/audio>', found '<{}>' instead", tokens.into_iter().map(|t| -> String { t.to_string() }).collect::<String>()))
           }
           if let TokenKind::Text(_t) = &tokens[0].kind {
-            let path = tokens[0].clone();
-            // TODO Handle music path somehow
-            let music = GameAudio {
-              path: path,
-              sound_effect: false,
-            };
-
-            return Ok((ParseNode::new(Expr::Audio(music)), index))
+            let music = parse_audio(program, &tokens[0], false)?;
+            Ok((ParseNode::new(Expr::Audio(music)), index))
           } else {
-            return Err("Incorrect type in play music action, must be in the format '<path/to/audio>'".to_string())
+            Err("Incorrect type in play music action, must be in the format '<path/to/audio>'".to_string())
           }
         },
         Err(msg) => Err(msg),
@@ -1637,14 +2197,14 @@ fn parse_token(program: &Program, pos: usize) -> Result<(ParseNode, usize), Stri
           }
           text.push(program.tokens[index-1].clone());
           let game_text = GameText {
-            text: text,
+            text,
             itallic: false,
             bold: false,
             color: 0,
           };
           Ok((ParseNode::new(Expr::Text(game_text)), index))
         },
-        Err(msg) => return Err(msg),
+        Err(msg) => Err(msg),
       }
     }, 
     TokenKind::OpenSquareBracket => {
@@ -1663,7 +2223,7 @@ fn parse_token(program: &Program, pos: usize) -> Result<(ParseNode, usize), Stri
           }
           Ok((ParseNode::new(Expr::Goto(tokens[0].clone())), index+1))
         },
-        Err(msg) => return Err(msg),
+        Err(msg) => Err(msg),
       }
     }, 
     TokenKind::Percent => {
@@ -1681,8 +2241,8 @@ fn parse_token(program: &Program, pos: usize) -> Result<(ParseNode, usize), Stri
           let action = InventoryAction::Check;
           let item = GameItem {
             name: var,
-            action: action,
-            inventory: inventory,
+            action,
+            inventory,
           };
           Ok((ParseNode::new(Expr::Require(item)), index))
         },
@@ -1699,6 +2259,10 @@ fn parse_token(program: &Program, pos: usize) -> Result<(ParseNode, usize), Stri
             TokenKind::Keyword(t) => {
               if t == "BREAK" {
                 t
+              } else if t == "NOUNDO" {
+                return Ok((ParseNode::new(Expr::NoUndo), index))
+              } else if t == "ENDING" {
+                return Ok((ParseNode::new(Expr::Ending), index))
               } else {
                 return Err(format!("Expected to find the keyword 'BREAK' inside '|..|', but found '|{}|' instead", t))
               }
@@ -1706,6 +2270,8 @@ fn parse_token(program: &Program, pos: usize) -> Result<(ParseNode, usize), Stri
             TokenKind::Text(t) => {
               if t.starts_with("DELAY") {
                 return Ok((ParseNode::new(Expr::Delay(tokens[0].clone())), index))
+              } else if t == "STOP" || t.starts_with("STOP ") {
+                return Ok((ParseNode::new(Expr::Stop(parse_stop(program, &tokens[0])?)), index))
               } else {
                 return Err(format!("Expected to find the keyword 'BREAK' inside '|..|', but found '|{}|' instead", t))
               }
@@ -1722,19 +2288,23 @@ fn parse_token(program: &Program, pos: usize) -> Result<(ParseNode, usize), Stri
     TokenKind::Pound => {
       match program.get_scope(i, TokenKind::Pound) {
         Ok((tokens, index)) => {
-          for tok in tokens.iter() {
-            match &tok.kind {
-              TokenKind::Text(_t) => (),
-              other => return Err(format!("Bolded text '#..#' only supports string objects however {} was found", token_kind_to_string(&other))),
-            }
+          if tokens.len() != 1 {
+            let error_type = String::from("Highlighted nouns must be in the format '#noun#' on a single line");
+            return Err(program.get_error_msg(&error_type, program.tokens[i].index, program.tokens[index-1].index+1));
+          }
+          if let TokenKind::Text(_t) = &tokens[0].kind {
+            // The '#'s are kept so the text still reads as it did in the script, see text_spans.
+            let game_text = GameText {
+              text: vec![program.tokens[i].clone(), tokens[0].clone(), program.tokens[index-1].clone()],
+              itallic: false,
+              bold: false,
+              color: 0,
+            };
+            Ok((ParseNode::new(Expr::Text(game_text)), index))
+          } else {
+            let error_type = format!("Highlighted nouns '#..#' only support text however {} was found", token_kind_to_string(&tokens[0].kind));
+            Err(program.get_error_msg(&error_type, program.tokens[i].index, program.tokens[index-1].index+1))
           }
-          let game_text = GameText {
-            text: tokens,
-            itallic: false,
-            bold: true,
-            color: 0,
-          };
-          Ok((ParseNode::new(Expr::Text(game_text)), index))
         },
         Err(msg) => Err(msg),
       }
@@ -1746,7 +2316,7 @@ fn parse_token(program: &Program, pos: usize) -> Result<(ParseNode, usize), Stri
           for tok in tokens.iter() {
             match &tok.kind {
               TokenKind::Text(_t) => (),
-              other => return Err(format!("Italliciesed text '~..~' only supports string objects however {} was found", token_kind_to_string(&other))),
+              other => return Err(format!("Italliciesed text '~..~' only supports string objects however {} was found", token_kind_to_string(other))),
             }
           }
           let game_text = GameText {
@@ -1768,13 +2338,13 @@ fn parse_token(program: &Program, pos: usize) -> Result<(ParseNode, usize), Stri
 fn parse_pipe(program: &Program, pos: usize) -> Result<(ParseNode, usize), String> {
   match program.get_scope(pos, TokenKind::Pipe) {
     Ok((tokens, index)) => {
-      if tokens.len() == 0 || !match_token_kind(&tokens[0].kind, &TokenKind::Keyword("BREAK".to_string())) {
-        return Err(format!("Unexpected symbols found inside of '|...|', found: {}", tokens.into_iter().map(|t| -> String { t.to_string() }).collect::<String>() ))
+      if tokens.is_empty() || !match_token_kind(&tokens[0].kind, &TokenKind::Keyword("BREAK".to_string())) {
+        Err(format!("Unexpected symbols found inside of '|...|', found: {}", tokens.into_iter().map(|t| -> String { t.to_string() }).collect::<String>() ))
       } else {
-        return Ok((ParseNode::new(Expr::Break), index))
+        Ok((ParseNode::new(Expr::Break), index))
       }
     },
-    Err(msg) => return Err(msg),
+    Err(msg) => Err(msg),
   }
 }
 
@@ -1783,18 +2353,18 @@ fn parse_inventory_modification(program: &Program, pos: usize) -> Result<(ParseN
   if program.tokens[pos].kind != TokenKind::Plus && program.tokens[pos].kind != TokenKind::Minus {
     return Err(format!("ICE: Starting parse_inventory_modification on wrong token, found '{}' instead", program.tokens[pos].to_string()));
   }
-  let positive = if program.tokens[pos].kind == TokenKind::Plus { true } else { false };
+  let positive = program.tokens[pos].kind == TokenKind::Plus;
   let mut new_pos = pos;
   let mut count = 0;
   if positive {
     while program.tokens[new_pos].kind == TokenKind::Plus {
-      count = count + 1;
-      new_pos = new_pos + 1;
+      count += 1;
+      new_pos += 1;
     }
   } else {
     while program.tokens[new_pos].kind == TokenKind::Minus {
-      count = count + 1;
-      new_pos = new_pos + 1;
+      count += 1;
+      new_pos += 1;
     }
   }
 
@@ -1823,33 +2393,33 @@ fn parse_caret(program: &Program, pos: usize) -> Result<(ParseNode, usize), Stri
 
   match program.get_scope(pos, TokenKind::Caret) {
     Ok((tokens, index)) => {
-      if tokens.len() == 0 {
-        return Err(format!("Unexpected symbols found inside of '^...^', found: {}", tokens.into_iter().map(|t| -> String { t.to_string() }).collect::<String>()))
+      if tokens.is_empty() {
+        Err(format!("Unexpected symbols found inside of '^...^', found: {}", tokens.into_iter().map(|t| -> String { t.to_string() }).collect::<String>()))
       } else {
         match program.tokens[pos+1].kind {
           TokenKind::Plus | TokenKind::Minus => {
-            match parse_inventory_modification(&program, pos+1) {
+            match parse_inventory_modification(program, pos+1) {
               Ok((node, new_pos)) => {
                 if new_pos != index-1 {
-                  return Err(format!("Unexpected tokens in ^...^: {}", program.tokens[new_pos..index-1].into_iter().map(|t| -> String { t.to_string() }).collect::<String>()))
+                  Err(format!("Unexpected tokens in ^...^: {}", program.tokens[new_pos..index-1].iter().map(|t| -> String { t.to_string() }).collect::<String>()))
                 } else {
-                  return Ok((node, index))
+                  Ok((node, index))
                 }
               },
-              Err(msg) => return Err(msg)
+              Err(msg) => Err(msg)
             }
           },
-          _ => return Err(format!("Unexpected token '{}' inside of ^...^ expression", program.tokens[pos+1].to_string()))
+          _ => Err(format!("Unexpected token '{}' inside of ^...^ expression", program.tokens[pos+1].to_string()))
         }
       }
     },
-    Err(msg) => return Err(msg),
+    Err(msg) => Err(msg),
   }
 }
 
 fn parse_scope(program: &Program, start_pos: usize, end_pos: usize) -> Result<Vec<ParseNode>, String> {
   if start_pos == end_pos {
-    return Err(format!("Recived an empty scope"))
+    return Err("Recived an empty scope".to_string())
   }
 
   //print!("Scope-Start index {}: {}\nScope-End index {}: {}\n", start_pos, program.tokens[start_pos].to_string(), end_pos, program.tokens[end_pos].to_string());
@@ -1858,7 +2428,7 @@ fn parse_scope(program: &Program, start_pos: usize, end_pos: usize) -> Result<Ve
   while i < (end_pos-1) {
     //print!("i: {}\ntoken: {}\n\n", i, program.tokens[i].to_string());
     match program.tokens[i].kind {
-      TokenKind::Newline => i = i + 1,
+      TokenKind::Newline => i += 1,
       //TokenKind::OpenCurlyBrace => return Err("Unexpected '{{' encountered!".to_string()),
       //TokenKind::CloseCurlyBrace => return Err("Unexpected '}}' encountered!".to_string()),
       //TokenKind::Pipe => {
@@ -1881,7 +2451,15 @@ fn parse_scope(program: &Program, start_pos: usize, end_pos: usize) -> Result<Ve
       //}
       //_ => return Err(format!("TODO: {}", program.tokens[i].to_string())),
       _ => {
-        i = match parse_token(&program, i) {
+        i = match parse_token(program, i) {
+          // Text that follows text on the same line is the same paragraph, see text_to_string.
+          Ok((ParseNode { value: Expr::Text(game_text), .. }, new_index)) if i > 0 && matches!(program.tokens[i-1].kind, TokenKind::Text(_) | TokenKind::Pound) => {
+            match nodes.last_mut() {
+              Some(ParseNode { value: Expr::Text(previous), .. }) => previous.text.extend(game_text.text),
+              _ => nodes.push(ParseNode::new(Expr::Text(game_text))),
+            }
+            new_index
+          },
           Ok((node, new_index)) => {
             nodes.push(node);
             new_index
@@ -1912,11 +2490,17 @@ fn parse_section(program: &Program, pos: usize, token: String) -> Result<(ParseN
     Err(msg) => return Err(msg),
   };
   let mut requirements: Vec<GameItem> = Vec::new();
+  let mut priority = None;
   while program.tokens[new_pos].kind != TokenKind::OpenCurlyBrace {
     match &program.tokens[new_pos].kind {
-      TokenKind::Newline => new_pos = new_pos + 1,
+      TokenKind::Newline => new_pos += 1,
+      TokenKind::Pipe if priority.is_none() => {
+        let (tokens, index) = program.get_scope(new_pos, TokenKind::Pipe)?;
+        priority = Some(parse_priority(program, &tokens, &program.tokens[new_pos])?);
+        new_pos = index;
+      },
       TokenKind::Ampersand | TokenKind::Percent | TokenKind::Dollar => {
-        let item = match parse_token(&program, new_pos) {
+        let item = match parse_token(program, new_pos) {
           Ok((node, i)) => {
             new_pos = i;
             match &node.value {
@@ -1948,35 +2532,224 @@ fn parse_section(program: &Program, pos: usize, token: String) -> Result<(ParseN
     },
     Err(msg) => return Err(msg),
   };
-  if scope.len() == 0 {
-    return Err(format!("Found an empty scope"));
+  if scope.is_empty() {
+    return Err("Found an empty scope".to_string());
   }
   let scope_end = match program.find_prev_token(TokenKind::CloseCurlyBrace, new_pos) {
     Some(pos) => pos,
     None => return Err(format!("'{} |{}|' is missing a '}}' token", token, name.to_string())),
   };
 
-  let scope_nodes = match parse_scope(&program, scope_start, scope_end) {
-    Ok(nodes) => nodes,
-    Err(msg) => return Err(msg),
-  };
+  let scope_nodes = parse_scope(program, scope_start, scope_end)?;
   
   //print!("\n\n");
+  if token == "TEST" {
+    if !requirements.is_empty() || priority.is_some() {
+      return Err(format!("'TEST |{}|' cannot have requirements, list the items to check inside of it instead", name.to_string()));
+    }
+    for node in scope_nodes.iter() {
+      match &node.value {
+        Expr::Text(_) | Expr::Goto(_) | Expr::Require(_) => (),
+        other => return Err(format!("Expected a command, [[room]] or inventory item in 'TEST |{}|' but found '{}'", name.to_string(), expr_to_string(other))),
+      }
+    }
+    let test = GameTest {
+      name,
+      scope: scope_nodes,
+    };
+    return Ok((ParseNode::new(Expr::Test(test)), new_pos));
+  }
+
   if token == "ROOM" {
     let room = GameRoom {
-      name: name,
-      requirements: requirements,
+      name,
+      requirements,
+      priority: priority.unwrap_or(0),
       scope: scope_nodes,
     };
-    return Ok((ParseNode::new(Expr::Room(room)), new_pos));
+    Ok((ParseNode::new(Expr::Room(room)), new_pos))
   } else {
     let action = GameAction {
       action: program.tokens[pos].clone(),
-      name: name,
-      requirements: requirements,
+      name,
+      requirements,
+      priority: priority.unwrap_or(0),
       scope: scope_nodes,
     };
-    return Ok((ParseNode::new(Expr::Action(action)), new_pos));
+    Ok((ParseNode::new(Expr::Action(action)), new_pos))
+  }
+}
+
+// Parses the SOUNDS section, which names the sounds that rooms and actions play:
+//
+//   SOUNDS {
+//   door_slam: sounds/SFX/doorSlam.mp3 volume 0.8
+//   train: sounds/BGM/trainAmb.mp3 once
+//   }
+//
+// Each entry is an id ending in ':', the path of the file and then any options, see
+// parse_audio_options. They are the defaults for wherever the sound is played. Entries are split
+// up by their ids rather than by lines, as the web frontend joins the lines of the script together.
+fn parse_sounds(program: &Program, pos: usize) -> Result<(ParseNode, usize), String> {
+  let (tokens, new_pos) = program.check_token(pos, TokenKind::Keyword("SOUNDS".to_string()))
+    .and_then(|i| program.eat_whitespace_tokens(i))
+    .and_then(|i| program.get_scope(i, TokenKind::OpenCurlyBrace))?;
+
+  let mut words = Vec::new();
+  for token in tokens.iter() {
+    match &token.kind {
+      TokenKind::Text(_t) => words.extend(split_words(token)),
+      TokenKind::Newline => (),
+      _ => return Err(token_error(program, "Expected 'id: path/to/audio' in SOUNDS", token)),
+    }
+  }
+
+  let mut sounds: Vec<GameSound> = Vec::new();
+  let mut i = 0;
+  while i < words.len() {
+    let id = match words[i].to_string().strip_suffix(':') {
+      Some(id) if !id.is_empty() => Token::new(TokenKind::Text(id.to_string()), words[i].index),
+      _ => return Err(token_error(program, &format!("Expected the id of a sound followed by ':' in SOUNDS but found '{}'", words[i].to_string()), &words[i])),
+    };
+    let path = match words.get(i+1) {
+      Some(path) if !path.to_string().ends_with(':') => path.clone(),
+      _ => return Err(token_error(program, &format!("Expected a path after '{}:' in SOUNDS", id.to_string()), &words[i])),
+    };
+    let (options, used) = parse_audio_options(program, &words[i+2..])?;
+    i += 2 + used;
+    if let Some(word) = words.get(i).filter(|w| !w.to_string().ends_with(':')) {
+      return Err(unknown_audio_option(program, word, &id));
+    }
+    sounds.push(GameSound { id, path, options });
+  }
+  Ok((ParseNode::new(Expr::Sounds(sounds)), new_pos))
+}
+
+// The error message for the token along with the line of the script it is on.
+fn token_error(program: &Program, message: &str, token: &Token) -> String {
+  program.get_error_msg(&message.to_string(), token.index, token.index + token.to_string().chars().count())
+}
+
+// Splits a text token into its words, each with its own position in the program.
+fn split_words(token: &Token) -> Vec<Token> {
+  let mut words = Vec::new();
+  let mut offset = 0;
+  for word in token.to_string().split(' ') {
+    if !word.is_empty() {
+      words.push(Token::new(TokenKind::Text(word.to_string()), token.index + offset));
+    }
+    offset += word.chars().count() + 1;
+  }
+  words
+}
+
+fn unknown_audio_option(program: &Program, word: &Token, sound: &Token) -> String {
+  let message = format!("Unknown option '{}' for the sound '{}', expected 'volume', 'loop', 'once', 'channel', 'fade' or 'caption'", word.to_string(), sound.to_string());
+  token_error(program, &message, word)
+}
+
+// Reads the options that follow the path of a sound up to the first word that is not one:
+//
+//   volume 0.5       A volume from 0 to 1.
+//   loop / once      Whether the sound repeats.
+//   channel voice    The channel it plays on, music replaces whatever is playing on its channel.
+//   fade 2000        The milliseconds it fades in over, the music it replaces fades out as it does.
+//   caption "Thud"   What the sound tells the player, shown instead of it when captions are on.
+fn parse_audio_options(program: &Program, words: &[Token]) -> Result<(AudioOptions, usize), String> {
+  let mut options = AudioOptions::default();
+  let mut i = 0;
+  while i < words.len() {
+    let value = words.get(i+1).map(|w| w.to_string());
+    match words[i].to_string().as_str() {
+      "loop" => options.looping = Some(true),
+      "once" => options.looping = Some(false),
+      "volume" => {
+        match value.and_then(|v| v.parse::<f32>().ok()).filter(|v| (0.0..=1.0).contains(v)) {
+          Some(volume) => options.volume = Some(volume),
+          None => return Err(token_error(program, "Expected 'volume' to be followed by a number from 0 to 1", &words[i])),
+        }
+        i += 1;
+      },
+      "channel" => {
+        match value.filter(|v| !v.ends_with(':')) {
+          Some(channel) => options.channel = Some(channel),
+          None => return Err(token_error(program, "Expected 'channel' to be followed by the name of a channel", &words[i])),
+        }
+        i += 1;
+      },
+      "fade" => {
+        match value.and_then(|v| v.parse::<u32>().ok()) {
+          Some(fade) => options.fade = Some(fade),
+          None => return Err(token_error(program, "Expected 'fade' to be followed by a number of milliseconds", &words[i])),
+        }
+        i += 1;
+      },
+      "caption" => {
+        // The caption is in quotes and runs up to the word that closes them.
+        let start = i + 1;
+        let end = words.iter().skip(start).position(|w| w.to_string().len() > 1 && w.to_string().ends_with('"'));
+        match (words.get(start), end) {
+          (Some(first), Some(end)) if first.to_string().starts_with('"') => {
+            let caption: Vec<String> = words[start..=start+end].iter().map(|w| w.to_string()).collect();
+            options.caption = Some(caption.join(" ").trim_matches('"').to_string());
+            i = start + end;
+          },
+          _ => return Err(token_error(program, "Expected 'caption' to be followed by text in quotes, ie 'caption \"A door slams shut\"'", &words[i])),
+        }
+      },
+      _ => break,
+    }
+    i += 1;
+  }
+  Ok((options, i))
+}
+
+// Parses the inside of '<path/to/audio>' or '*path/to/audio*', which is a path or the id of a
+// sound followed by its options, ie '<train volume 0.5 fade 2000>'.
+fn parse_audio(program: &Program, token: &Token, sound_effect: bool) -> Result<GameAudio, String> {
+  let words = split_words(token);
+  let (options, used) = parse_audio_options(program, &words[1..])?;
+  if let Some(word) = words.get(used + 1) {
+    return Err(unknown_audio_option(program, word, &words[0]));
+  }
+  Ok(GameAudio::new(words[0].clone(), sound_effect, None, options, &AudioOptions::default()))
+}
+
+// Parses '|STOP|', '|STOP channel|' or '|STOP channel milliseconds|'. The music channel is
+// stopped when none is given.
+fn parse_stop(program: &Program, token: &Token) -> Result<GameStop, String> {
+  let words = split_words(token);
+  let fade = match words.get(2) {
+    Some(word) => match word.to_string().parse::<u32>() {
+      Ok(fade) => fade,
+      Err(_msg) => return Err(token_error(program, "Expected '|STOP channel milliseconds|'", word)),
+    },
+    None => 0,
+  };
+  if let Some(word) = words.get(3) {
+    return Err(token_error(program, "Expected '|STOP channel milliseconds|'", word));
+  }
+  Ok(GameStop {
+    token: token.clone(),
+    channel: words.get(1).map(|w| w.to_string()).unwrap_or_else(|| "music".to_string()),
+    fade,
+  })
+}
+
+// Parses the '|PRIORITY n|' that can follow the requirements of a section, the tokens are the ones
+// between the pipes.
+fn parse_priority(program: &Program, tokens: &[Token], pipe: &Token) -> Result<u32, String> {
+  let expected = "Expected '|PRIORITY n|' after the requirements, where n is a whole number";
+  let words = match tokens {
+    [token] if matches!(token.kind, TokenKind::Text(_)) => split_words(token),
+    _ => return Err(token_error(program, expected, pipe)),
+  };
+  match words.as_slice() {
+    [keyword, number] if keyword.to_string() == "PRIORITY" => match number.to_string().parse::<u32>() {
+      Ok(priority) => Ok(priority),
+      Err(_msg) => Err(token_error(program, expected, number)),
+    },
+    _ => Err(token_error(program, expected, &tokens[0])),
   }
 }
 
@@ -1989,8 +2762,8 @@ fn parse(program: &Program) -> Result<Vec<ParseNode>, String> {
   }
   while pos < program.tokens.len() {
     match &program.tokens[pos].kind {
-      TokenKind::Keyword(t) if (t == "ROOM" || t == "HELP" || t == "MISC" || t == "EXAMINE" || t == "USE" || t == "TAKE" || t == "TALK" || t == "GO") => {
-        match parse_section(&program, pos, t.to_string()) {
+      TokenKind::Keyword(t) if (t == "ROOM" || t == "HELP" || t == "MISC" || t == "EXAMINE" || t == "USE" || t == "TAKE" || t == "TALK" || t == "GO" || t == "TEST") => {
+        match parse_section(program, pos, t.to_string()) {
           Ok((n, i)) => {
             nodes.push(n);
             pos = i;
@@ -1999,6 +2772,12 @@ fn parse(program: &Program) -> Result<Vec<ParseNode>, String> {
         }
       },
 
+      TokenKind::Keyword(t) if t == "SOUNDS" => {
+        let (n, i) = parse_sounds(program, pos)?;
+        nodes.push(n);
+        pos = i;
+      },
+
       TokenKind::Newline => pos += 1,
       _ => {
         let (row, col) = program.get_location_with_token(&program.tokens[pos]);
@@ -2020,19 +2799,517 @@ fn parse(program: &Program) -> Result<Vec<ParseNode>, String> {
 
 #[test]
 fn test_token_index() {
-  let args: Vec<String> = std::env::args().collect();
-  let current_path = Path::new(&args[0]).parent().unwrap();
-  let root_path = current_path.join("..").join("..").join("..");
-  let src_path = root_path.join("src");
-  let narrative_path = src_path.join("narrative.txt");
-  let mut program = read_program(&narrative_path);
+  let root_path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  let narrative_path = root_path.join("www").join("narrative.txt");
+  let mut program = read_program(&narrative_path).expect("The story should be readable");
   match lex(&program) {
     Ok(tok) => program.tokens = tok,
-    Err(msg) => assert!(false, "Error: {}\n", msg),
+    Err(msg) => panic!("Error: {}\n", msg),
   }
 
-  for i in 0..program.tokens.len() {
-    assert!(i == program.tokens[i].index, format!("Invalid token index: Found {} but expected {}.\n\"{}\"", program.tokens[i].index, i, program.get_line(i)));
+  // The index of a token is where its text starts in the program.
+  for token in program.tokens.iter() {
+    let text = token.to_string();
+    let found = program.substr(token.index, cmp::min(token.index + text.chars().count(), program.text.len()));
+    assert!(found == text, "Invalid token index {}: Found \"{}\" but expected \"{}\".\n\"{}\"", token.index, found, text, program.get_line_with_token(token));
   }
 }
 
+
+#[test]
+fn test_scope_events() {
+  let game = compile("ROOM [init] {\nHello there\n|BREAK|\n<sounds/BGM/trainAmb.mp3>\n^+glass^\n}\n".to_string());
+  let result = game.start();
+  let kinds: Vec<GameEventKind> = result.get_events().iter().map(|e| e.kind()).collect();
+  assert_eq!(kinds, vec![GameEventKind::Text, GameEventKind::Break, GameEventKind::Music, GameEventKind::InventoryChange]);
+  assert_eq!(result.to_string(), "Hello there \n|BREAK|\n<sounds/BGM/trainAmb.mp3>\n");
+}
+
+#[test]
+fn test_json_serialization() {
+  let game = compile("ROOM [init] {\nThe sign reads \"Staff Only\"\n^++init^\n}\nEXAMINE [sign] &init& {\nIt is a sign.\n}\n".to_string());
+  let value: serde_json::Value = serde_json::from_str(&game.to_json()).expect("Game JSON should be valid");
+  assert_eq!(value["rooms"]["init"]["actions"][0]["name"], "sign");
+
+  let result = game.start();
+  let value: serde_json::Value = serde_json::from_str(&result.to_json()).expect("Result JSON should be valid");
+  assert_eq!(value["events"][0]["text"], "The sign reads \"Staff Only\"");
+  assert_eq!(value["state"]["inventory"]["room"]["init"][0], "init");
+
+  let state = GameState::from_json(result.to_state().to_json()).expect("State JSON should load");
+  assert!(state.eq(&result.to_state()));
+}
+
+#[test]
+fn test_goto_enters_room() {
+  let game = compile("ROOM [init] {\nStart\n^++init^\n}\nGO [east] &init& {\nYou walk east.\n[[hall]]\n}\nROOM [hall] &hall& {\nBack again\n}\nROOM [hall] {\nFirst time\n^++hall^\n}\n".to_string());
+  let result = game.query("GO".to_string(), "east".to_string(), &game.start().to_state());
+  let kinds: Vec<GameEventKind> = result.get_events().iter().map(|e| e.kind()).collect();
+  assert_eq!(kinds, vec![GameEventKind::Text, GameEventKind::RoomChange, GameEventKind::Text, GameEventKind::InventoryChange]);
+  assert_eq!(result.get_state().get_room_name(), "hall");
+  assert_eq!(result.get_state().get_room_index(), 1);
+  assert!(game.get_current_room(result.get_state()).requirements.is_empty());
+}
+
+#[test]
+fn test_save_and_load() {
+  let game = compile("ROOM [init] {\nStart\n^+key^\n}\nTAKE [key] {\nYou take the key.\n}\n".to_string());
+  let state = game.start().to_state();
+  let loaded = game.load(game.save(&state)).expect("A save should load into the game that made it");
+  assert!(loaded.eq(&state));
+
+  let other = compile("ROOM [init] {\nStart\n^+lamp^\n}\n".to_string());
+  assert!(other.load(game.save(&state)).is_err());
+  // Fixing the prose keeps old saves working.
+  let edited = compile("ROOM [init] {\nThe start\n^+key^\n}\nTAKE [key] {\nYou pick up the #key#.\n}\n".to_string());
+  assert_eq!(edited.fingerprint(), game.fingerprint());
+  assert!(edited.load(game.save(&state)).is_ok());
+  // A priority decides which section runs, so it is part of the story.
+  let prioritized = compile("ROOM [init] {\nStart\n^+key^\n}\nTAKE [key] |PRIORITY 1| {\nYou take the key.\n}\n".to_string());
+  assert_ne!(prioritized.fingerprint(), game.fingerprint());
+  assert!(game.load("{\"version\":99}".to_string()).is_err());
+}
+
+#[test]
+fn test_undo_and_redo() {
+  let game = compile("ROOM [init] {\nStart\n}\nTAKE [key] {\nYou take the key.\n^+key^\n}\nUSE [key] $key$ {\nThe door locks behind you.\n|NOUNDO|\n^-key^\n}\n".to_string());
+  let mut history = history::TurnHistory::new(10);
+  let start = game.start().to_state();
+  let take = game.command("take key".to_string(), &start);
+  history.record(&start, &take);
+
+  let undone = history.undo(take.get_state()).expect("Taking the key should be undoable");
+  assert!(undone.eq(&start));
+  let redone = history.redo(&undone).expect("Undoing should be redoable");
+  assert!(redone.eq(take.get_state()));
+
+  let locked = game.command("use key".to_string(), &redone);
+  history.record(&redone, &locked);
+  assert!(!history.can_undo());
+}
+
+#[test]
+fn test_transcript_replay() {
+  let script = "ROOM [init] {\n<sounds/BGM/trainAmb.mp3>\nStart\n}\nTAKE [key] {\nYou take the key.\n^+key^\n}\nMISC [loadstone] {\nThe stone glows.\n}\n";
+  let game = compile(script.to_string());
+  let path = std::env::temp_dir().join(format!("reflections_test_transcript_{}.jsonl", std::process::id()));
+  let path = path.to_str().expect("The temp dir should be valid unicode");
+
+  let mut recorder = transcript::Recorder::create(&game, path).expect("The transcript should be created");
+  let mut session = session::Session::new(&game, history::TurnHistory::default());
+  recorder.record(None, session.get_result()).expect("The start should be recorded");
+  recorder.record(Some("take key"), session.input("take key")).expect("The turn should be recorded");
+  // An answer that starts with 'load' is run again rather than taken for a load.
+  recorder.record(Some("loadstone"), session.input("loadstone")).expect("The turn should be recorded");
+
+  let replay = transcript::replay_file(&game, path).expect("The transcript should replay");
+  assert!(replay.fingerprint_matches);
+  assert!(replay.difference.is_none());
+  assert_eq!(replay.turns, 3);
+
+  // Only the prose changed, so the story is the same version but the text differs.
+  let changed = compile(script.replace("You take the key.", "You pocket the key.").to_string());
+  let replay = transcript::replay_file(&changed, path).expect("The transcript should replay");
+  assert!(replay.fingerprint_matches);
+  assert_eq!(replay.turns, 1);
+  assert!(replay.difference.expect("The changed text should be found").starts_with("Turn 2 'take key'"));
+
+  let changed = compile(script.replace("The stone glows.", "The stone hums.").to_string());
+  let replay = transcript::replay_file(&changed, path).expect("The transcript should replay");
+  assert!(replay.difference.expect("The changed text should be found").starts_with("Turn 3 'loadstone'"));
+
+
+  // Version 1 transcripts were recorded before the state kept its audio.
+  let text = std::fs::read_to_string(path).expect("The transcript should be readable");
+  let _ = std::fs::remove_file(path);
+  let without_audio: Vec<String> = text.lines().map(|line| {
+    let mut value: serde_json::Value = serde_json::from_str(line).expect("The transcript should be JSON");
+    if let Some(state) = value.get_mut("state").and_then(|state| state.as_object_mut()) {
+      state.remove("audio");
+    }
+    value.to_string()
+  }).collect();
+  let version_2 = without_audio.join("\n");
+  assert!(transcript::replay(&game, &version_2).expect("The transcript should replay").difference.is_some());
+  let version_1 = version_2.replacen("\"version\":2", "\"version\":1", 1);
+  assert!(transcript::replay(&game, &version_1).expect("The transcript should replay").difference.is_none());
+}
+
+#[test]
+fn test_playtest() {
+  let game = compile("ROOM [init] {\nStart\n}\nTAKE [key] {\nYou take the key.\n^+key^\n}\n".to_string());
+  let playtest = playtest::run(&game, "? Start\n!$key$\n> take key\n? You take the key.\n$key$\n= init\n").expect("The playtest should parse");
+  assert_eq!(playtest.steps, 2);
+  assert!(playtest.failure.is_none());
+
+  let playtest = playtest::run(&game, "> take key\n!$key$\n> take lamp\n").expect("The playtest should parse");
+  assert_eq!(playtest.steps, 1);
+  assert!(playtest.failure.expect("Having the key should fail").starts_with("Turn 'take key' on line 1 failed:\nLine 2: expected not to have $key$"));
+  assert!(playtest::run(&game, "take key\n").is_err());
+}
+
+#[test]
+fn test_script_tests() {
+  let script = "ROOM [init] {\nStart\n}\nTAKE [key] {\nYou take the key.\n^+key^\n}\nTEST [take_key] {\ntake key\n$key$\n[[init]]\n}\nTEST [wrong_room] {\n[[hall]]\n}\n";
+  let game = compile(script.to_string());
+  assert_eq!(game.get_tests().len(), 2);
+  assert!(game.start().get_events().iter().all(|e| e.kind() == GameEventKind::Text));
+
+  let playtest = playtest::run_test(&game, &game.get_tests()[0]);
+  assert!(playtest.failure.is_none());
+  assert_eq!(playtest.steps, 2);
+  let playtest = playtest::run_test(&game, &game.get_tests()[1]);
+  assert_eq!(playtest.failure.expect("The test should fail"), "The start of the game failed:\nexpected to be in ROOM |hall| but was in ROOM |init|");
+}
+
+#[test]
+fn test_check_gotos() {
+  let game = compile("ROOM [init] {\nStart\n}\nGO [east] {\n[[hal]]\n}\nROOM [hall] {\nA hall\n}\nROOM [attic] {\nDust\n[[hall]]\n}\n".to_string());
+  let diagnostics = check::check(&game);
+  let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+  assert_eq!(messages, vec![
+    "Goto to the unknown ROOM |hal|, did you mean 'hall'?",
+    "ROOM |hall| is never reached from ROOM |init|",
+    "ROOM |attic| is never reached from ROOM |init|",
+  ]);
+  assert_eq!(check::count_errors(&diagnostics), 1);
+}
+
+#[test]
+fn test_check_items() {
+  let game = compile("ROOM [init] {\nStart\n^+key^\n^++lit^\n}\nUSE [key] $key$ &lit& {\nClick\n^---dor^\n}\nUSE [door] %door% {\nIt opens\n^+++doors^\n}\n".to_string());
+  let messages: Vec<String> = check::check_items(&game).into_iter().map(|d| d.message).collect();
+  assert_eq!(messages, vec![
+    "%door% is required but never added, so this section can never be used",
+    "%doors% is added but never required by any section",
+    "%dor% is removed but never added",
+  ]);
+}
+
+#[test]
+fn test_solver() {
+  let script = "ROOM [init] {\nStart\n}\nTAKE [key] {\nYou take the key.\n^+key^\n}\nGO [east] {\n[[hall]]\n}\nGO [west] $key$ {\n[[end]]\n}\nUSE [key] $key$ {\nIt breaks.\n^-key^\n}\nUSE [lamp] $lamp$ {\nLight\n}\nROOM [hall] {\nA hall with no way back\n}\nGO [up] {\nThere are no stairs\n}\nROOM [end] {\nThe end\n}\n";
+  let game = compile(script.to_string());
+  let exploration = solver::explore(&game, &solver::SolverLimits::default());
+  assert_eq!(exploration.states.len(), 5);
+  assert_eq!(exploration.endings.len(), 1);
+  assert_eq!(exploration.path_to(exploration.endings[0]), vec!["take key", "go west"]);
+  assert!(exploration.failures.is_empty());
+
+  let stuck: Vec<Vec<String>> = exploration.points_of_no_return().into_iter().map(|s| exploration.path_to(s)).collect();
+  assert_eq!(stuck, vec![vec!["go east"], vec!["take key", "go east"]]);
+  let unreachable: Vec<String> = exploration.unreachable_sections(&game).into_iter().map(|d| d.message).collect();
+  assert_eq!(unreachable, vec!["'USE |lamp|' in ROOM |init| is never run"]);
+
+  // The same failure in every state of a room is reported once.
+  let game = compile("ROOM [init] {\nStart\n}\nTAKE [key] {\n^+key^\n}\nTAKE [map] {\n^+map^\n}\nGO [north] {\n[[nowhere]]\n}\n".to_string());
+  let exploration = solver::explore(&game, &solver::SolverLimits::default());
+  assert_eq!(exploration.failures.len(), 4);
+  let groups = exploration.failure_groups();
+  assert_eq!(groups.len(), 1);
+  assert_eq!((groups[0].room.as_str(), groups[0].first.command.as_str(), groups[0].count), ("init", "go north", 4));
+  assert!(exploration.path_to(groups[0].first.state).is_empty());
+
+  // A death is an ending, but only the rooms marked '|ENDING|' finish the story.
+  let script = "ROOM [init] {\nStart\n}\nTAKE [key] {\n^+key^\n}\nUSE [poison] {\n[[dead]]\n}\nGO [west] $key$ {\n[[end]]\n}\nGO [east] {\n[[hall]]\n}\nROOM [hall] {\nA hall with no way back\n}\nGO [up] {\nThere are no stairs\n}\nROOM [dead] {\nYou died\n}\nROOM [end] {\n|ENDING|\nThe end\n}\n";
+  let exploration = solver::explore(&compile(script.to_string()), &solver::SolverLimits::default());
+  assert_eq!(exploration.endings.len(), 3);
+  assert_eq!(exploration.finished.len(), 1);
+  let stuck: Vec<Vec<String>> = exploration.points_of_no_return().into_iter().map(|s| exploration.path_to(s)).collect();
+  assert_eq!(stuck, vec![vec!["go east"], vec!["take key", "go east"]]);
+  let exploration = solver::explore(&compile(script.replace("|ENDING|\n", "")), &solver::SolverLimits::default());
+  assert_eq!(exploration.finished.len(), 3);
+}
+
+#[test]
+fn test_walkthroughs() {
+  let script = "ROOM [init] {\nStart\n}\nTAKE [key] {\n^+key^\n}\nGO [east] {\n[[hall]]\n}\nGO [west] $key$ {\n[[end]]\n}\nROOM [hall] {\nA hall\n}\nGO [west] {\n[[init]]\n}\nGO [down] {\n[[cellar]]\n}\nROOM [end] {\nThe end\n}\nROOM [cellar] {\nDark\n}\n";
+  let game = compile(script.to_string());
+  let exploration = solver::explore(&game, &solver::SolverLimits::default());
+
+  let endings = solver::walkthroughs(&exploration, &solver::Goal::Endings);
+  let commands: Vec<&Vec<String>> = endings.iter().map(|w| &w.commands).collect();
+  assert_eq!(commands, vec![&vec!["take key".to_string(), "go west".to_string()], &vec!["go east".to_string(), "go down".to_string()]]);
+  assert_eq!(endings[0].to_playtest(), "> take key\n> go west\n= end\n$key$\n");
+  let playtest = playtest::run(&game, &endings[0].to_playtest()).expect("The walkthrough should be a valid playtest");
+  assert!(playtest.failure.is_none());
+
+  let hall = solver::walkthroughs(&exploration, &solver::Goal::parse("hall"));
+  assert_eq!(hall[0].commands, vec!["go east"]);
+  let key = solver::walkthroughs(&exploration, &solver::Goal::parse("$key$"));
+  assert_eq!(key[0].commands, vec!["take key"]);
+  assert!(solver::walkthroughs(&exploration, &solver::Goal::parse("attic")).is_empty());
+}
+
+#[test]
+fn test_check_assets() {
+  let root_path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("www");
+  let script = "ROOM [init] {\n<sounds/BGM/trainAmb.mp3>\nStart\n*sounds/SFX/doorSlams.mp3*\n}\n";
+  let game = compile(script.to_string());
+  let messages: Vec<String> = check::check_assets(&game, &root_path).into_iter().map(|d| d.message).collect();
+  assert_eq!(messages, vec![format!("The sound 'sounds/SFX/doorSlams.mp3' does not exist in {}, did you mean 'sounds/SFX/doorSlam.mp3'?", root_path.display())]);
+  assert!(compile_with_assets(script.to_string(), &root_path).is_err());
+  assert!(check::unreferenced_assets(&game, &root_path).contains(&"sounds/SFX/doorSlam.mp3".to_string()));
+
+  let program = {
+    let mut program = read_program_from_string(&script.to_string());
+    program.tokens = lex(&program).expect("The script should lex");
+    program
+  };
+  assert_eq!(check::cue_sheet(&game, &program), "line,room,section,kind,path,sound,caption\n2,\"init\",\"ROOM [init]\",\"music\",\"sounds/BGM/trainAmb.mp3\",\"\",\"\"\n4,\"init\",\"ROOM [init]\",\"sound_effect\",\"sounds/SFX/doorSlams.mp3\",\"\",\"\"\n");
+
+  // Commas and quotes stay inside their field.
+  let script = "SOUNDS {\nslam: sounds/SFX/doorSlam.mp3 caption \"A door slams, loudly\"\n}\nROOM [init] {\nStart\n*slam*\n}\n";
+  let game = compile(script.to_string());
+  let mut program = read_program_from_string(&script.to_string());
+  program.tokens = lex(&program).expect("The script should lex");
+  assert_eq!(check::cue_sheet(&game, &program), "line,room,section,kind,path,sound,caption\n6,\"init\",\"ROOM [init]\",\"sound_effect\",\"sounds/SFX/doorSlam.mp3\",\"slam\",\"A door slams, loudly\"\n");
+  assert_eq!(check::csv_field("The \"mirror\""), "\"The \"\"mirror\"\"\"");
+}
+
+#[test]
+fn test_sound_registry() {
+  let script = "SOUNDS {\ntrain: sounds/BGM/trainAmb.mp3 volume 0.5\nslam: sounds/SFX/doorSlam.mp3 loop\nunused: sounds/SFX/glassFilled.mp3\n}\nROOM [init] {\n<train>\nStart\n*slam*\n*sounds/SFX/doorSlam.mp3*\n}\n";
+  let game = compile(script.to_string());
+  let cues: Vec<(GameEventKind, AudioCue)> = game.start().get_events().iter().filter_map(|e| match e {
+    GameEvent::Music(cue) | GameEvent::SoundEffect(cue) => Some((e.kind(), cue.clone())),
+    _ => None,
+  }).collect();
+  assert_eq!(cues, vec![
+    (GameEventKind::Music, AudioCue { path: "sounds/BGM/trainAmb.mp3".to_string(), volume: 0.5, looping: true, channel: "music".to_string(), fade: 0, caption: None }),
+    (GameEventKind::SoundEffect, AudioCue { path: "sounds/SFX/doorSlam.mp3".to_string(), volume: 1.0, looping: true, channel: "effects".to_string(), fade: 0, caption: None }),
+    (GameEventKind::SoundEffect, AudioCue { path: "sounds/SFX/doorSlam.mp3".to_string(), volume: 1.0, looping: false, channel: "effects".to_string(), fade: 0, caption: None }),
+  ]);
+  let messages: Vec<String> = check::check_sounds(&game).into_iter().map(|d| d.message).collect();
+  assert_eq!(messages, vec!["The sound 'unused' is never played"]);
+
+  // The lines of the script are joined together by the web frontend.
+  let joined = compile(script.replace('\n', " "));
+  assert_eq!(joined.get_sounds().len(), 3);
+
+  let mut program = read_program_from_string(&script.replace("<train>", "<trian>"));
+  let msg = build_game(&mut program).err().expect("An unknown sound should not compile");
+  assert!(msg.contains("Unknown sound 'trian', did you mean 'train'?"), "{}", msg);
+  let mut program = read_program_from_string(&script.replace("volume 0.5", "volume 5"));
+  assert!(build_game(&mut program).is_err());
+}
+
+#[test]
+fn test_audio_directives() {
+  let script = "SOUNDS {\nwind: sounds/BGM/wind.mp3 channel ambience volume 0.4\n}\nROOM [init] {\n<wind fade 2000>\n<sounds/BGM/trainAmb.mp3 fade 1500>\n*sounds/SFX/doorSlam.mp3 volume 0.3 channel voice*\nStart\n|STOP ambience 3000|\n|STOP|\n}\n";
+  let game = compile(script.to_string());
+  let result = game.start();
+  let audio: Vec<String> = result.get_events().iter().filter_map(|e| match e {
+    GameEvent::Music(cue) | GameEvent::SoundEffect(cue) => Some(format!("{:?} {} {} {} {}", e.kind(), cue.path, cue.channel, cue.volume, cue.fade)),
+    GameEvent::Stop { channel, fade } => Some(format!("Stop {} {}", channel, fade)),
+    _ => None,
+  }).collect();
+  assert_eq!(audio, vec![
+    "Music sounds/BGM/wind.mp3 ambience 0.4 2000",
+    "Music sounds/BGM/trainAmb.mp3 music 1 1500",
+    "SoundEffect sounds/SFX/doorSlam.mp3 voice 0.3 0",
+    "Stop ambience 3000",
+    "Stop music 0",
+  ]);
+  assert!(json::to_json(&result).contains("{\"type\":\"stop\",\"channel\":\"ambience\",\"fade\":3000}"));
+  assert!(!result.to_string().contains("STOP"));
+
+  let mut program = read_program_from_string(&script.replace("fade 1500", "fade slowly"));
+  assert!(build_game(&mut program).is_err());
+  let mut program = read_program_from_string(&script.replace("fade 1500", "loudly"));
+  let msg = build_game(&mut program).err().expect("An unknown option should not compile");
+  assert!(msg.contains("Unknown option 'loudly' for the sound 'sounds/BGM/trainAmb.mp3'"), "{}", msg);
+}
+
+#[test]
+fn test_resume_audio() {
+  let script = "ROOM [init] {\n<sounds/BGM/trainAmb.mp3>\nStart\n}\nGO [east] {\n[[hall]]\n}\nUSE [radio] {\n<sounds/BGM/radio.mp3 channel ambience>\n|STOP music 1000|\n}\nROOM [hall] {\n<sounds/BGM/hall.mp3>\nA hall\n}\nGO [west] {\n[[init]]\n}\n";
+  let game = compile(script.to_string());
+  let start = game.start();
+  let radio = game.command("use radio".to_string(), start.get_state());
+  let audio: Vec<(&String, &String)> = radio.get_state().get_audio().iter().map(|(channel, cue)| (channel, &cue.path)).collect();
+  assert_eq!(audio, vec![(&"ambience".to_string(), &"sounds/BGM/radio.mp3".to_string())]);
+
+  // Resuming plays the music of the state, not the music of the room.
+  let resumed = game.resume(radio.get_state());
+  let events: Vec<String> = resumed.get_events().iter().filter_map(|e| match e {
+    GameEvent::Music(cue) => Some(format!("play {}", cue.path)),
+    GameEvent::Stop { channel, .. } => Some(format!("stop {}", channel)),
+    _ => None,
+  }).collect();
+  assert_eq!(events, vec!["play sounds/BGM/radio.mp3"]);
+  assert!(resumed.get_state().eq(radio.get_state()));
+
+  // Undoing the radio stops it and starts the music it stopped.
+  let mut session = session::Session::new(&game, history::TurnHistory::default());
+  session.input("use radio");
+  let undone = session.input("undo");
+  let events: Vec<String> = undone.get_events().iter().filter_map(|e| match e {
+    GameEvent::Music(cue) => Some(format!("play {}", cue.path)),
+    GameEvent::Stop { channel, .. } => Some(format!("stop {}", channel)),
+    _ => None,
+  }).collect();
+  assert_eq!(events, vec!["stop ambience", "play sounds/BGM/trainAmb.mp3"]);
+
+  // Saves from before the audio was kept load with nothing playing.
+  let old_save = format!("{{\"version\":1,\"game\":\"{}\",\"state\":{{\"room\":\"init\",\"room_index\":0,\"inventory\":{{\"personal\":[],\"room\":{{}},\"global\":[]}}}}}}", game.fingerprint());
+  let state = game.load(old_save).expect("A version 1 save should still load");
+  assert!(state.get_audio().is_empty());
+
+  // Only the text of the room is shown again, its breaks, delays and modifications are not.
+  let game = compile("ROOM [init] {\nStart\n|BREAK|\n|DELAY 24000|\n^+key^\nStill here\n}\n".to_string());
+  let state = game.start().to_state();
+  let resumed = game.resume(&state);
+  assert!(resumed.get_events().iter().all(|e| matches!(e, GameEvent::Text { .. })), "{:?}", resumed.get_events());
+  assert_eq!(resumed.to_string(), "Start Still here \n");
+  assert!(resumed.get_state().eq(&state));
+}
+
+#[test]
+fn test_captions() {
+  let script = "SOUNDS {\nslam: sounds/SFX/doorSlam.mp3 caption \"A door slams shut\"\n}\nROOM [init] {\nStart\n*slam*\n<sounds/BGM/trainAmb.mp3 caption \"The train rumbles\">\n*sounds/SFX/glassFilled.mp3*\n}\n";
+  let mut game = compile(script.to_string());
+  let captions: Vec<Option<String>> = game.start().get_events().iter().filter_map(|e| match e {
+    GameEvent::Music(cue) | GameEvent::SoundEffect(cue) => Some(cue.caption.clone()),
+    _ => None,
+  }).collect();
+  assert_eq!(captions, vec![Some("A door slams shut".to_string()), Some("The train rumbles".to_string()), None]);
+
+  game.set_captions(true);
+  let result = game.start();
+  assert_eq!(result.to_string(), "Start [A door slams shut] [The train rumbles] \n");
+  assert_eq!(result.get_state().get_audio()["music"].path, "sounds/BGM/trainAmb.mp3");
+  assert_eq!(game.resume(result.get_state()).to_string(), "[The train rumbles] Start \n");
+
+  let mut program = read_program_from_string(&script.replace("\"A door slams shut\"", "\"A door"));
+  assert!(build_game(&mut program).is_err());
+}
+
+#[test]
+fn test_cli_exit_codes() {
+  let narrative_path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("www").join("narrative.txt");
+  let script = narrative_path.display().to_string();
+  let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+  assert_eq!(cli::main(&args(&["test", "--script", &script])), 0);
+  assert_eq!(cli::main(&args(&["--test", "--script", &script])), 0);
+  assert_eq!(cli::main(&args(&["tokens", "--script", "does/not/exist.txt"])), 2);
+  assert_eq!(cli::main(&args(&["frobnicate"])), 2);
+  assert_eq!(cli::main(&args(&["check", "--script", "does/not/exist.txt"])), 2);
+  assert_eq!(cli::main(&args(&["replay", "--script", &script])), 2);
+}
+
+#[test]
+fn test_print_tokens() {
+  let mut program = read_program_from_string(&"ROOM [init] {\nStart\n}\n".to_string());
+  program.tokens = lex(&program).expect("The script should lex");
+  let mut out = Vec::new();
+  program.print_tokens(&mut out);
+  assert_eq!(String::from_utf8(out).expect("The tokens should be utf-8"), "ROOM [ init ] { \n Start \n } \n ");
+}
+
+#[test]
+fn test_terminal_render() {
+  let plain = terminal::Renderer::new(false);
+  let ansi = terminal::Renderer::new(true);
+  let text = "Take the #glass# ~now~ @8a0303 or die@ email@example.com";
+  assert_eq!(plain.render(text, false, false, 0), "Take the glass now or die email@example.com");
+  assert_eq!(ansi.render(text, false, false, 0), "Take the \x1b[1mglass\x1b[0m \x1b[3mnow\x1b[0m \x1b[38;2;138;3;3mor die\x1b[0m email@example.com");
+  assert_eq!(ansi.render("#Yes#", true, false, 0), "\x1b[1;3mYes\x1b[0m");
+  assert_eq!(ansi.render("Hi", false, false, 0x8a0303), "\x1b[38;2;138;3;3mHi\x1b[0m");
+}
+
+#[test]
+fn test_terminal_wrap() {
+  assert_eq!(terminal::wrap("The door to the  hallway sits closed", 15), vec!["The door to the", "hallway sits", "closed"]);
+  assert_eq!(terminal::wrap("a \x1b[1mmirror\x1b[0m here", 8), vec!["a \x1b[1mmirror\x1b[0m", "here"]);
+  assert_eq!(terminal::wrap("unbreakable", 4), vec!["unbreakable"]);
+  assert!(terminal::wrap("", 80).is_empty());
+
+  // A line split where the lexer skips a character is still one paragraph.
+  let game = compile("ROOM [init] {\n“Hello,” he says.\nGoodbye\n}\n".to_string());
+  let texts: Vec<String> = game.start().get_events().iter().filter_map(|e| match e {
+    GameEvent::Text { text, .. } => Some(text.clone()),
+    _ => None,
+  }).collect();
+  assert_eq!(texts, vec!["Hello, he says.", "Goodbye"]);
+}
+
+#[test]
+fn test_interactables() {
+  let script = "ROOM [init] {\nA #glass# and a #poster#. Go #east# or type #go west#.\n}\nEXAMINE [glass] {\nEmpty\n}\nGO [east] {\n[[init]]\n}\n";
+  let game = compile(script.to_string());
+  let (text, spans) = match &game.start().get_events()[0] {
+    GameEvent::Text { text, spans, .. } => (text.clone(), spans.clone()),
+    other => panic!("Expected text but found {:?}", other),
+  };
+  assert_eq!(text, "A #glass# and a #poster#. Go #east# or type #go west#.");
+  let interactable = |text: &str, noun: &str| TextSpan::Interactable { text: text.to_string(), noun: noun.to_string() };
+  assert_eq!(spans, vec![
+    TextSpan::Text("A ".to_string()), interactable("glass", "glass"),
+    TextSpan::Text(" and a ".to_string()), interactable("poster", "poster"),
+    TextSpan::Text(". Go ".to_string()), interactable("east", "east"),
+    TextSpan::Text(" or type ".to_string()), interactable("go west", "west"),
+    TextSpan::Text(".".to_string()),
+  ]);
+  assert!(game.start().to_json().contains("{\"type\":\"interactable\",\"text\":\"go west\",\"noun\":\"west\"}"));
+
+  let warnings: Vec<String> = check::check_interactables(&game).iter().map(|d| d.message.clone()).collect();
+  assert_eq!(warnings, vec!["The highlighted '#poster#' has no EXAMINE section in ROOM |init|"]);
+
+  let mut program = read_program_from_string(&script.replace("#poster#", "#poster"));
+  assert!(build_game(&mut program).is_err());
+}
+
+#[test]
+fn test_verb_coverage() {
+  let script = "ROOM [init] {\nA #bed# and a lamp. The door is #east#.\n}\nEXAMINE [bed] %good% {\nSoft\n}\nEXAMINE [bed] {\nHard\n}\nUSE [bed] %good% {\nZzz\n}\nTAKE [lamp] {\nGot it\n^+++good^\n}\nGO [east] {\n[[end]]\n}\nROOM [end] {\nThe end\n}\n";
+  let game = compile(script.to_string());
+  let exploration = solver::explore(&game, &solver::SolverLimits::default());
+  let report = coverage::verb_coverage(&game, &exploration);
+  let text = coverage::render(&report, &exploration);
+  assert_eq!(text, "ROOM |init| in 2 states\n  bed: EXAMINE in %good% and any state, USE in %good% but missing at the start, TAKE missing, TALK missing\n  east: GO in any state\n  lamp: EXAMINE missing, USE missing, TAKE in any state, TALK missing\n\n");
+}
+
+#[test]
+fn test_ambiguous_sections() {
+  let script = "ROOM [init] {\nStart\n}\nTAKE [key] {\n^+++key^\n}\nTAKE [map] {\n^+++map^\n}\nEXAMINE [door] %key% {\nLocked\n}\nEXAMINE [door] %map% {\nMarked\n}\nEXAMINE [door] {\nA door\n}\nEXAMINE [door] %map% {\nMarked again\n}\nUSE [door] %key% %map% {\nBoth\n}\nUSE [door] %map% |PRIORITY 1| {\nMap first\n}\n";
+  let game = compile(script.to_string());
+  let shadowed: Vec<String> = check::check_shadowed(&game).iter().map(|d| d.message.clone()).collect();
+  assert_eq!(shadowed, vec![
+    "EXAMINE |door| with %map% never runs, EXAMINE |door| with %map% passes whenever it does and is chosen over it. Give it requirements the other does not have or a higher |PRIORITY n|",
+    "USE |door| with %key% %map% never runs, USE |door| with %map% |PRIORITY 1| passes whenever it does and is chosen over it. Give it requirements the other does not have or a higher |PRIORITY n|",
+  ]);
+
+  let exploration = solver::explore(&game, &solver::SolverLimits::default());
+  let ambiguous: Vec<String> = exploration.ambiguous_sections(&game).iter().map(|d| d.message.clone()).collect();
+  assert_eq!(ambiguous, vec![
+    "EXAMINE |door| with %key% and EXAMINE |door| with %map% both pass after 'take key, take map', only the first one in the script runs. Give one of them more requirements or a higher |PRIORITY n|",
+  ]);
+}
+
+#[test]
+fn test_section_priority() {
+  let script = "ROOM [init] {\nStart\n}\nTAKE [key] {\n^+++key^\n}\nTAKE [map] {\n^+++map^\n}\nEXAMINE [door] {\nA door\n}\nEXAMINE [door] %key% {\nLocked\n}\nEXAMINE [door] %key% %map% {\nBoth\n}\nUSE [door] {\nNothing happens\n}\nUSE [door] %key% {\nUnlocked\n}\nUSE [door] |PRIORITY 2| {\nStuck\n}\n";
+  let game = compile(script.to_string());
+  let mut state = game.start().get_state().clone();
+  assert_eq!(game.command("examine door".to_string(), &state).to_string(), "A door \n");
+  state = game.command("take key".to_string(), &state).get_state().clone();
+  // The most specific section runs wherever it is in the script.
+  assert_eq!(game.command("examine door".to_string(), &state).to_string(), "Locked \n");
+  state = game.command("take map".to_string(), &state).get_state().clone();
+  assert_eq!(game.command("examine door".to_string(), &state).to_string(), "Both \n");
+  // A priority wins over more requirements.
+  assert_eq!(game.command("use door".to_string(), &state).to_string(), "Stuck \n");
+
+  let mut program = read_program_from_string(&script.replace("|PRIORITY 2|", "|PRIORITY high|"));
+  let msg = build_game(&mut program).err().expect("A priority that is not a number should not compile");
+  assert!(msg.contains("Expected '|PRIORITY n|' after the requirements"), "{}", msg);
+}
+
+#[test]
+fn test_dot() {
+  let script = "ROOM [init] {\nStart\n}\nGO [east] {\n[[hall]]\n}\nUSE [door] %key% {\n[[hall]]\n}\nUSE [door] {\n[[hal]]\n}\nROOM [hall] {\nA hall\n}\nGO [west] {\n[[init]]\n}\nROOM [attic] {\nDust\n[[hall]]\n}\n";
+  let game = compile(script.to_string());
+  assert_eq!(dot::to_dot(&game, false), "digraph story {\n  node [shape=box];\n  \"attic\" [style=dashed];\n  \"hall\";\n  \"init\" [style=bold];\n  \"hal\" [color=red];\n  \"attic\" -> \"hall\" [label=\"ROOM [attic]\"];\n  \"hall\" -> \"init\" [label=\"GO [west]\"];\n  \"init\" -> \"hall\" [label=\"GO [east]\"];\n  \"init\" -> \"hall\" [label=\"USE [door]\"];\n  \"init\" -> \"hal\" [label=\"USE [door]\"];\n}\n");
+  assert!(dot::to_dot(&game, true).contains("  \"init\" -> \"hall\" [label=\"USE [door] %key%\"];\n"));
+}
@@ -1,5 +1,3 @@
-mod lib;
-
 fn main() {
-  lib::run();
+  reflections::run();
 }
@@ -0,0 +1,229 @@
+// Scripted playthroughs of a game, checked step by step.
+//
+// A playtest is a text file of commands, each followed by the checks to make on what it did:
+//
+//   // The checks before the first command are made on the start of the game.
+//   ? Would you like another glass of water
+//   > yes
+//   ? He sets down your now full glass
+//   &full_glass&
+//   > go east
+//   = your_room
+//   !$lantern_on$
+//
+//   > command    The line typed by the player, including 'undo' and 'redo'.
+//   ? text       The text of the turn contains the snippet. Runs of whitespace count as one space.
+//   = room       The player is in the room.
+//   $item$       The item is in the personal inventory. '&item&' checks the inventory of the
+//                current room and '%item%' the global one.
+//   !$item$      The item is not in the inventory.
+//
+// Blank lines and lines starting with '//' are skipped. A turn that ends in an error fails even
+// without a check, since a playthrough of the intended solution should never hit one. The
+// playtest stops at the first turn with a failed check, as every turn after it depends on it.
+//
+// A TEST section in the script is run the same way. Its lines of text are the commands, a goto
+// checks the room and a requirement checks the inventory:
+//
+//   TEST [into_the_mirror] {
+//   yes
+//   go east
+//   [[your_room]]
+//   &your_room&
+//   }
+
+use super::{Expr, Game, GameEvent, GameItem, GameResult, GameTest, InventoryAction, InventoryKind, Token, TokenKind, tokens_to_string};
+use super::history::TurnHistory;
+use super::session::Session;
+
+enum Check {
+  Output(String),
+  Room(String),
+  Item(GameItem, bool),
+}
+
+// Steps from a TEST section have no line numbers, the script is gone once it is compiled.
+struct Step {
+  line: Option<usize>,
+  command: Option<String>,
+  checks: Vec<(Option<usize>, Check)>,
+}
+
+pub struct Playtest {
+  // How many turns passed their checks, including the start of the game.
+  pub steps: usize,
+  // Every check that failed on the first turn that did not pass, if any.
+  pub failure: Option<String>,
+}
+
+// Reads an item written the way the script does, ie '$key$'.
+pub fn parse_item(text: &str) -> Option<GameItem> {
+  let inventory = match text.chars().next()? {
+    '$' => InventoryKind::Personal,
+    '&' => InventoryKind::Room,
+    '%' => InventoryKind::Global,
+    _ => return None,
+  };
+  let marker = &text[..1];
+  let name = text.strip_prefix(marker)?.strip_suffix(marker)?;
+  if name.is_empty() || name.contains(marker) {
+    return None;
+  }
+  Some(GameItem {
+    name: Token::new(TokenKind::Text(name.to_string()), 0),
+    action: InventoryAction::Check,
+    inventory,
+  })
+}
+
+fn parse_check(line: &str) -> Option<Check> {
+  if let Some(snippet) = line.strip_prefix('?') {
+    return Some(Check::Output(normalize(snippet)));
+  }
+  if let Some(room) = line.strip_prefix('=') {
+    return Some(Check::Room(room.trim().to_string()));
+  }
+  match line.strip_prefix('!') {
+    Some(item) => Some(Check::Item(parse_item(item.trim())?, false)),
+    None => Some(Check::Item(parse_item(line)?, true)),
+  }
+}
+
+fn parse_steps(text: &str) -> Result<Vec<Step>, String> {
+  let mut steps = vec![Step { line: None, command: None, checks: Vec::new() }];
+  for (i, line) in text.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with("//") {
+      continue;
+    }
+    if let Some(command) = line.strip_prefix('>') {
+      steps.push(Step { line: Some(i+1), command: Some(command.trim().to_string()), checks: Vec::new() });
+      continue;
+    }
+    let check = match parse_check(line) {
+      Some(check) => check,
+      None => return Err(format!("Line {}: expected '>', '?', '=' or an inventory item but found '{}'", i+1, line)),
+    };
+    steps.last_mut().expect("ICE: The playtest has no step for the start of the game").checks.push((Some(i+1), check));
+  }
+  Ok(steps)
+}
+
+fn test_steps(game_test: &GameTest) -> Vec<Step> {
+  let mut steps = vec![Step { line: None, command: None, checks: Vec::new() }];
+  for node in game_test.scope.iter() {
+    let check = match &node.value {
+      Expr::Text(game_text) => {
+        steps.push(Step { line: None, command: Some(tokens_to_string(&game_text.text).trim().to_string()), checks: Vec::new() });
+        continue;
+      },
+      Expr::Goto(room) => Check::Room(room.to_string()),
+      Expr::Require(game_item) => Check::Item(game_item.clone(), true),
+      _ => panic!("ICE: Found '{:?}' inside of TEST |{}|", node.value, game_test.name.to_string()),
+    };
+    steps.last_mut().expect("ICE: The test has no step for the start of the game").checks.push((None, check));
+  }
+  steps
+}
+
+fn normalize(text: &str) -> String {
+  text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn output_text(result: &GameResult) -> String {
+  let text: Vec<String> = result.get_events().iter().filter_map(|event| match event {
+    GameEvent::Text { text, .. } => Some(text.clone()),
+    GameEvent::Error(msg) => Some(msg.clone()),
+    _ => None,
+  }).collect();
+  normalize(&text.join(" "))
+}
+
+fn item_to_string(item: &GameItem) -> String {
+  let marker = match item.inventory {
+    InventoryKind::Personal => "$",
+    InventoryKind::Room => "&",
+    InventoryKind::Global => "%",
+  };
+  format!("{}{}{}", marker, item.name.to_string(), marker)
+}
+
+fn describe_line(line: &Option<usize>) -> String {
+  match line {
+    Some(line) => format!("Line {}: ", line),
+    None => String::new(),
+  }
+}
+
+// Describes every check of the step that the result does not pass.
+fn check_step(step: &Step, result: &GameResult) -> Vec<String> {
+  let mut failures = Vec::new();
+  let output = output_text(result);
+  for event in result.get_events() {
+    if let GameEvent::Error(msg) = event {
+      failures.push(format!("{}the turn ended in an error\n  {}", describe_line(&step.line), msg));
+    }
+  }
+
+  let state = result.get_state();
+  for (line, check) in step.checks.iter() {
+    match check {
+      Check::Output(snippet) if !output.contains(snippet.as_str()) => {
+        failures.push(format!("{}expected the text to contain\n  {}\nbut found\n  {}", describe_line(line), snippet, output));
+      },
+      Check::Room(room) if room != state.get_room_name() => {
+        failures.push(format!("{}expected to be in ROOM |{}| but was in ROOM |{}|", describe_line(line), room, state.get_room_name()));
+      },
+      Check::Item(item, present) if state.inventory.check_item(item, state.get_room_name()) != *present => {
+        let expected = if *present { "to have" } else { "not to have" };
+        failures.push(format!("{}expected {} {}\n  {}", describe_line(line), expected, item_to_string(item), state.inventory.to_string(state.get_room_name()).trim_end().replace('\n', "\n  ")));
+      },
+      _ => (),
+    }
+  }
+  failures
+}
+
+fn describe_step(step: &Step) -> String {
+  match (&step.command, &step.line) {
+    (Some(c), Some(line)) => format!("Turn '{}' on line {}", c, line),
+    (Some(c), None) => format!("Turn '{}'", c),
+    (None, _) => "The start of the game".to_string(),
+  }
+}
+
+fn run_steps(game: &Game, steps: &[Step]) -> Playtest {
+  let mut report = Playtest {
+    steps: 0,
+    failure: None,
+  };
+  let mut session = Session::new(game, TurnHistory::default());
+  for step in steps.iter() {
+    let result = match &step.command {
+      Some(command) => session.input(command),
+      None => session.get_result(),
+    };
+    let failures = check_step(step, result);
+    if !failures.is_empty() {
+      report.failure = Some(format!("{} failed:\n{}", describe_step(step), failures.join("\n")));
+      return report;
+    }
+    report.steps += 1;
+  }
+  report
+}
+
+pub fn run(game: &Game, playtest: &str) -> Result<Playtest, String> {
+  Ok(run_steps(game, &parse_steps(playtest)?))
+}
+
+pub fn run_test(game: &Game, game_test: &GameTest) -> Playtest {
+  run_steps(game, &test_steps(game_test))
+}
+
+pub fn run_file(game: &Game, path: &str) -> Result<Playtest, String> {
+  match std::fs::read_to_string(path) {
+    Ok(text) => run(game, &text).map_err(|msg| format!("{}: {}", path, msg)),
+    Err(msg) => Err(format!("Could not read {}: {}", path, msg)),
+  }
+}
@@ -0,0 +1,138 @@
+// Saving and loading of a GameState.
+//
+// A save is a single line of JSON holding the save format version, a fingerprint of the compiled
+// game and the state itself (see json.rs for the layout of the state):
+//
+//   {"version":3,"game":"9c1185a5c5e9fc54","state":{"room":"init","room_index":0,"inventory":{..},"audio":{..}}}
+//
+// Version 1 saves have no audio and load with nothing playing. Before version 3 the fingerprint
+// left out the priority of the sections, so those saves only load into stories without one.
+//
+// The fingerprint is taken from the structure of the compiled rooms and actions, their names,
+// requirements, priorities, modifications and gotos, so fixing a typo in the prose keeps old saves working
+// while adding, removing or reordering a section rejects them.
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use super::{Expr, Game, GameItem, GameState, InventoryAction, ParseNode, inventory_kind_to_string, json};
+
+// Bump whenever the layout of a save changes, older versions must keep loading.
+pub const SAVE_VERSION: u32 = 3;
+
+#[derive(Serialize, Deserialize)]
+struct SaveFile {
+  version: u32,
+  game: String,
+  state: GameState,
+}
+
+// 64 bit FNV-1a, used over std's hasher since its output is not guaranteed between releases.
+fn fnv1a(text: &str) -> u64 {
+  let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+  for byte in text.bytes() {
+    hash ^= u64::from(byte);
+    hash = hash.wrapping_mul(0x0100_0000_01b3);
+  }
+  hash
+}
+
+fn describe_item(item: &GameItem) -> String {
+  let action = match item.action {
+    InventoryAction::Add => "+",
+    InventoryAction::Remove => "-",
+    InventoryAction::Check => "",
+  };
+  format!("{}{}:{}", action, inventory_kind_to_string(&item.inventory), item.name.to_string())
+}
+
+// The requirements and priority of a section, then the modifications and gotos of its scope in
+// order. A priority of 0 is left out so that stories without any keep their fingerprint.
+fn describe_section(key: &mut String, header: String, requirements: &[GameItem], priority: u32, scope: &[ParseNode]) {
+  key.push_str(&header);
+  for item in requirements.iter() {
+    key.push_str(&format!(" {}", describe_item(item)));
+  }
+  if priority > 0 {
+    key.push_str(&format!(" |PRIORITY {}|", priority));
+  }
+  for node in scope.iter() {
+    match &node.value {
+      Expr::Modify(item) => key.push_str(&format!(" {}", describe_item(item))),
+      Expr::Goto(token) => key.push_str(&format!(" [[{}]]", token.to_string())),
+      _ => (),
+    }
+  }
+  key.push('\n');
+}
+
+impl Game {
+  // Only what a save depends on goes into it, see the top of the file.
+  fn structure(&self) -> String {
+    let mut room_names: Vec<&String> = self.rooms.keys().collect();
+    room_names.sort();
+    let mut key = String::new();
+    for room_name in room_names {
+      let (rooms, actions) = &self.rooms[room_name];
+      for room in rooms.iter() {
+        describe_section(&mut key, format!("ROOM {}", room_name), &room.requirements, room.priority, &room.scope);
+      }
+      for action in actions.iter() {
+        describe_section(&mut key, format!("{} {} {}", room_name, action.action.to_string(), action.name.to_string()), &action.requirements, action.priority, &action.scope);
+      }
+    }
+    key
+  }
+
+  pub fn fingerprint(&self) -> String {
+    format!("{:016x}", fnv1a(&self.structure()))
+  }
+}
+
+#[wasm_bindgen]
+impl Game {
+  pub fn save(&self, state: &GameState) -> String {
+    let save = SaveFile {
+      version: SAVE_VERSION,
+      game: self.fingerprint(),
+      state: state.clone(),
+    };
+    json::to_json(&save)
+  }
+
+  pub fn load(&self, data: String) -> Result<GameState, String> {
+    let save: SaveFile = match serde_json::from_str(&data) {
+      Ok(save) => save,
+      Err(msg) => return Err(format!("Could not read the save: {}", msg)),
+    };
+    if save.version > SAVE_VERSION {
+      return Err(format!("The save is from a newer version of the game (save format {}, expected at most {})", save.version, SAVE_VERSION));
+    }
+    if save.game != self.fingerprint() {
+      return Err("The save was made for a different version of the story and cannot be loaded".to_string());
+    }
+
+    let state = save.state;
+    match self.rooms.get(state.get_room_name()) {
+      Some((rooms, _actions)) if state.get_room_index() < rooms.len() => Ok(state),
+      Some(_) => Err(format!("The save refers to ROOM |{}| variant {} which does not exist", state.get_room_name(), state.get_room_index())),
+      None => Err(format!("The save refers to ROOM |{}| which does not exist", state.get_room_name())),
+    }
+  }
+}
+
+// The file used by the terminal when 'save' or 'load' are given without one.
+pub const DEFAULT_SAVE_PATH: &str = "reflections.sav";
+
+pub fn save_to_file(game: &Game, state: &GameState, path: &str) -> Result<(), String> {
+  match std::fs::write(path, game.save(state) + "\n") {
+    Ok(_) => Ok(()),
+    Err(msg) => Err(format!("Could not write {}: {}", path, msg)),
+  }
+}
+
+pub fn load_from_file(game: &Game, path: &str) -> Result<GameState, String> {
+  match std::fs::read_to_string(path) {
+    Ok(data) => game.load(data.trim().to_string()),
+    Err(msg) => Err(format!("Could not read {}: {}", path, msg)),
+  }
+}
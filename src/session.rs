@@ -0,0 +1,60 @@
+// A game being played, fed one line of player input at a time.
+//
+// The session owns the latest result and the turn history, so the terminal, transcript replays
+// and anything else that drives the game by typed commands all handle 'undo' and 'redo' alike.
+
+use super::{Game, GameResult, GameState};
+use super::history::TurnHistory;
+
+pub struct Session<'a> {
+  game: &'a Game,
+  result: GameResult,
+  history: TurnHistory,
+}
+
+impl<'a> Session<'a> {
+  pub fn new(game: &'a Game, history: TurnHistory) -> Session<'a> {
+    Session {
+      game,
+      result: game.start(),
+      history,
+    }
+  }
+
+  pub fn get_result(&self) -> &GameResult {
+    &self.result
+  }
+
+  pub fn get_state(&self) -> &GameState {
+    self.result.get_state()
+  }
+
+  // Runs a line of input and returns what it produced.
+  pub fn input(&mut self, line: &str) -> &GameResult {
+    let state = self.result.to_state();
+    self.result = match line.trim() {
+      "undo" => match self.history.undo(&state) {
+        Some(previous) => self.game.resume_from(&state, &previous),
+        None => GameResult::error("There is nothing to undo".to_string(), state),
+      },
+      "redo" => match self.history.redo(&state) {
+        Some(next) => self.game.resume_from(&state, &next),
+        None => GameResult::error("There is nothing to redo".to_string(), state),
+      },
+      command => {
+        let turn = self.game.command(command.to_string(), &state);
+        self.history.record(&state, &turn);
+        turn
+      },
+    };
+    &self.result
+  }
+
+  // Replaces the state, ie after loading a save. The history belongs to the old state, so it
+  // is forgotten.
+  pub fn restore(&mut self, state: GameState) -> &GameResult {
+    self.history.clear();
+    self.result = self.game.resume_from(self.result.get_state(), &state);
+    &self.result
+  }
+}
@@ -0,0 +1,437 @@
+// Exploration of every state a game can reach, to find the ways a player can get stuck.
+//
+// The game is treated as a graph. Each GameState is a node and each command whose section's
+// requirements pass is an edge, starting from the state left by Game::start. States that are
+// equal by GameState::eq are the same node; they are keyed by their JSON, which holds the same
+// fields. An ending is any state in a room with no actions, since nothing can be done there.
+//
+// Not every ending is one the story is meant to reach, ie a room where the player died. The rooms
+// the author marked with '|ENDING|' are the ones the story is finished in, or every ending when no
+// room is marked. A dead end is a state from which none of them can be reached.
+//
+// The exploration is breadth first, so the path to every state is one of the shortest, which is
+// what the walkthroughs are made of.
+
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::panic;
+use super::{Expr, Game, GameEvent, GameItem, GameResult, GameState, find_room_index, json, parse_command};
+use super::check::{Diagnostic, is_subset, section_groups};
+use super::playtest;
+
+pub struct SolverLimits {
+  // States further than this many commands from the start are not explored.
+  pub max_depth: usize,
+  // The exploration stops once this many states have been found.
+  pub max_states: usize,
+}
+
+impl Default for SolverLimits {
+  fn default() -> SolverLimits {
+    SolverLimits {
+      max_depth: 200,
+      max_states: 100_000,
+    }
+  }
+}
+
+// A command that panicked or ended in an error.
+pub struct Failure {
+  pub state: usize,
+  pub command: String,
+  pub message: String,
+}
+
+// The failures of the same command in the same room with the same message, which are the same
+// mistake in the script however many states run into it.
+pub struct FailureGroup<'a> {
+  pub room: String,
+  // The first failure found, which has one of the shortest paths.
+  pub first: &'a Failure,
+  pub count: usize,
+}
+
+pub struct Exploration {
+  pub states: Vec<GameState>,
+  // The state each state was first reached from and the command that did it.
+  parents: Vec<Option<(usize, String)>>,
+  // Every command that leads from a state to a different one.
+  pub transitions: Vec<Vec<(String, usize)>>,
+  // Whether the commands of the state were tried, the limits can leave some states untried.
+  expanded: Vec<bool>,
+  // The endings in the order they were found, and whether each state is one of them.
+  pub endings: Vec<usize>,
+  is_ending: Vec<bool>,
+  // The endings the story is finished in, see the top of the file.
+  pub finished: Vec<usize>,
+  is_finished: Vec<bool>,
+  pub failures: Vec<Failure>,
+  // The room variants (room name, index) and actions (room name, index) that ran at least once.
+  rooms_run: BTreeSet<(String, usize)>,
+  actions_run: BTreeSet<(String, usize)>,
+  pub truncated: bool,
+}
+
+impl Exploration {
+  // The commands that lead from the start of the game to the state.
+  pub fn path_to(&self, mut state: usize) -> Vec<String> {
+    let mut commands = Vec::new();
+    while let Some((parent, command)) = &self.parents[state] {
+      commands.push(command.clone());
+      state = *parent;
+    }
+    commands.reverse();
+    commands
+  }
+
+  // The failures grouped by room, command and message, in the order they were first found.
+  pub fn failure_groups(&self) -> Vec<FailureGroup<'_>> {
+    let mut groups: Vec<FailureGroup> = Vec::new();
+    let mut index: HashMap<(String, &String, &String), usize> = HashMap::new();
+    for failure in self.failures.iter() {
+      let room = self.states[failure.state].get_room_name().to_string();
+      match index.get(&(room.clone(), &failure.command, &failure.message)) {
+        Some(i) => groups[*i].count += 1,
+        None => {
+          index.insert((room.clone(), &failure.command, &failure.message), groups.len());
+          groups.push(FailureGroup { room, first: failure, count: 1 });
+        },
+      }
+    }
+    groups
+  }
+
+  // The states from which the story cannot be finished. States that the limits left untried are
+  // counted as able to finish it, since nothing is known about them.
+  pub fn dead_ends(&self) -> Vec<usize> {
+    let mut incoming: Vec<Vec<usize>> = vec![Vec::new(); self.states.len()];
+    for (from, transitions) in self.transitions.iter().enumerate() {
+      for (_command, to) in transitions.iter() {
+        incoming[*to].push(from);
+      }
+    }
+
+    let mut alive = vec![false; self.states.len()];
+    let mut queue: VecDeque<usize> = (0..self.states.len()).filter(|i| self.is_finished[*i] || !self.expanded[*i]).collect();
+    for i in queue.iter() {
+      alive[*i] = true;
+    }
+    while let Some(state) = queue.pop_front() {
+      for from in incoming[state].iter() {
+        if !alive[*from] {
+          alive[*from] = true;
+          queue.push_back(*from);
+        }
+      }
+    }
+    (0..self.states.len()).filter(|i| !alive[*i]).collect()
+  }
+
+  // The dead ends that were first reached from a state that could still finish the story, ie the
+  // commands that make the game impossible to finish. Endings such as a death are left out, the
+  // story stops there on purpose.
+  pub fn points_of_no_return(&self) -> Vec<usize> {
+    let dead = self.dead_ends();
+    let mut is_dead = vec![false; self.states.len()];
+    for i in dead.iter() {
+      is_dead[*i] = true;
+    }
+    dead.into_iter().filter(|i| !self.is_ending[*i]).filter(|i| match &self.parents[*i] {
+      Some((parent, _command)) => !is_dead[*parent],
+      None => true,
+    }).collect()
+  }
+
+  // Every room variant and action that no explored command ever ran.
+  pub fn unreachable_sections(&self, game: &Game) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for (room_name, (rooms, actions)) in game.rooms.iter() {
+      for (i, room) in rooms.iter().enumerate() {
+        if !self.rooms_run.contains(&(room_name.to_string(), i)) {
+          diagnostics.push(Diagnostic::warning(format!("This variant of ROOM |{}| is never entered", room_name), &room.name));
+        }
+      }
+      for (i, action) in actions.iter().enumerate() {
+        if !self.actions_run.contains(&(room_name.to_string(), i)) {
+          diagnostics.push(Diagnostic::warning(format!("'{} |{}|' in ROOM |{}| is never run", action.action.to_string(), action.name.to_string(), room_name), &action.name));
+        }
+      }
+    }
+    diagnostics.sort_by_key(|d| d.token.index);
+    diagnostics
+  }
+
+  // Pairs of sections that both pass in a reachable state and are ranked the same, so only their
+  // order in the script decides which one runs (see choose_section). Sections with the same
+  // requirements as an earlier one are left to check::check_shadowed.
+  pub fn ambiguous_sections(&self, game: &Game) -> Vec<Diagnostic> {
+    let groups = section_groups(game);
+    let mut reported = BTreeSet::new();
+    let mut diagnostics = Vec::new();
+    for (state_index, state) in self.states.iter().enumerate() {
+      let room_name = state.get_room_name();
+      for (group_index, group) in groups.iter().enumerate().filter(|(_i, group)| group.room == room_name) {
+        let passing: Vec<usize> = (0..group.sections.len()).filter(|i| state.inventory.check_items(group.sections[*i].requirements, room_name)).collect();
+        let best = match passing.iter().map(|i| group.sections[*i].rank()).max() {
+          Some(best) => best,
+          None => continue,
+        };
+        let tied: Vec<usize> = passing.into_iter().filter(|i| group.sections[*i].rank() == best).collect();
+        for (position, other) in tied.iter().enumerate().skip(1) {
+          let second = &group.sections[*other];
+          let repeated = tied[..position].iter().any(|i| is_subset(group.sections[*i].requirements, second.requirements));
+          if repeated || !reported.insert((group_index, tied[0], *other)) {
+            continue;
+          }
+          let when = match self.path_to(state_index).as_slice() {
+            [] => "at the start".to_string(),
+            commands => format!("after '{}'", commands.join(", ")),
+          };
+          let message = format!("{} and {} both pass {}, only the first one in the script runs. Give one of them more requirements or a higher |PRIORITY n|",
+            group.describe(tied[0]), group.describe(*other), when);
+          diagnostics.push(Diagnostic::warning(message, second.token));
+        }
+      }
+    }
+    diagnostics.sort_by_key(|d| d.token.index);
+    diagnostics
+  }
+
+  fn add_state(&mut self, state: GameState, parent: Option<(usize, String)>, known: &mut HashMap<String, usize>) -> (usize, bool) {
+    let key = json::to_json(&state);
+    if let Some(i) = known.get(&key) {
+      return (*i, false);
+    }
+    let index = self.states.len();
+    known.insert(key, index);
+    self.states.push(state);
+    self.parents.push(parent);
+    self.transitions.push(Vec::new());
+    self.expanded.push(false);
+    self.is_ending.push(false);
+    self.is_finished.push(false);
+    (index, true)
+  }
+
+  // Works out which variant of each room the result passed through, by going over its events
+  // with the inventory the way Game::run_room does.
+  fn note_rooms(&mut self, game: &Game, before: &GameState, result: &GameResult) {
+    let mut room_name = before.get_room_name().to_string();
+    let mut inventory = before.inventory.clone();
+    for event in result.get_events().iter() {
+      match event {
+        GameEvent::InventoryChange(item) => inventory = inventory.modify(item, &room_name),
+        GameEvent::RoomChange(name) => {
+          room_name = name.to_string();
+          if let Ok(i) = find_room_index(&game.rooms, &room_name, &inventory) {
+            self.rooms_run.insert((room_name.to_string(), i));
+          }
+        },
+        _ => (),
+      }
+    }
+    let state = result.get_state();
+    self.rooms_run.insert((state.get_room_name().to_string(), state.get_room_index()));
+  }
+}
+
+// The lines a player could type in the state, one per action name in the room.
+fn commands(game: &Game, state: &GameState) -> Vec<String> {
+  let actions = match game.rooms.get(state.get_room_name()) {
+    Some((_rooms, actions)) => actions,
+    None => return Vec::new(),
+  };
+  let mut commands: Vec<String> = Vec::new();
+  for action in actions.iter() {
+    let command = match action.action.to_string().as_str() {
+      "MISC" | "HELP" => action.name.to_string(),
+      verb => format!("{} {}", verb.to_lowercase(), action.name.to_string()),
+    };
+    if !commands.contains(&command) {
+      commands.push(command);
+    }
+  }
+  commands
+}
+
+// The rooms with an '|ENDING|' in any of their variants.
+fn marked_endings(game: &Game) -> BTreeSet<&String> {
+  game.rooms.iter()
+    .filter(|(_name, (rooms, _actions))| rooms.iter().any(|room| room.scope.iter().any(|node| matches!(node.value, Expr::Ending))))
+    .map(|(name, _room)| name)
+    .collect()
+}
+
+fn is_ending(game: &Game, state: &GameState) -> bool {
+  match game.rooms.get(state.get_room_name()) {
+    Some((_rooms, actions)) => actions.is_empty(),
+    None => false,
+  }
+}
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+  match payload.downcast::<String>() {
+    Ok(msg) => *msg,
+    Err(payload) => match payload.downcast::<&str>() {
+      Ok(msg) => msg.to_string(),
+      Err(_) => "Unknown panic".to_string(),
+    },
+  }
+}
+
+pub fn explore(game: &Game, limits: &SolverLimits) -> Exploration {
+  let mut exploration = Exploration {
+    states: Vec::new(),
+    parents: Vec::new(),
+    transitions: Vec::new(),
+    expanded: Vec::new(),
+    endings: Vec::new(),
+    is_ending: Vec::new(),
+    finished: Vec::new(),
+    is_finished: Vec::new(),
+    failures: Vec::new(),
+    rooms_run: BTreeSet::new(),
+    actions_run: BTreeSet::new(),
+    truncated: false,
+  };
+  let mut known = HashMap::new();
+  let mut depths = vec![0];
+  let marked = marked_endings(game);
+
+  let start = game.start();
+  let (first, _new) = exploration.add_state(start.to_state(), None, &mut known);
+  exploration.note_rooms(game, start.get_state(), &start);
+  let mut queue = VecDeque::new();
+  queue.push_back(first);
+  while let Some(from) = queue.pop_front() {
+    let state = exploration.states[from].clone();
+    let ending = is_ending(game, &state);
+    let finished = match marked.is_empty() {
+      true => ending,
+      false => marked.contains(&state.get_room_name().to_string()),
+    };
+    if finished {
+      exploration.finished.push(from);
+      exploration.is_finished[from] = true;
+    }
+    if ending {
+      exploration.endings.push(from);
+      exploration.is_ending[from] = true;
+      exploration.expanded[from] = true;
+      continue;
+    }
+    if depths[from] >= limits.max_depth {
+      exploration.truncated = true;
+      continue;
+    }
+    exploration.expanded[from] = true;
+
+    for command in commands(game, &state) {
+      let (action, name) = parse_command(&command);
+      let index = match game.find_action_index(&action, &name, &state) {
+        Ok(i) => i,
+        Err(_msg) => continue,
+      };
+      exploration.actions_run.insert((state.get_room_name().to_string(), index));
+
+      let result = match panic::catch_unwind(panic::AssertUnwindSafe(|| game.command(command.clone(), &state))) {
+        Ok(result) => result,
+        Err(payload) => {
+          exploration.failures.push(Failure { state: from, command, message: format!("Panicked: {}", panic_message(payload)) });
+          continue;
+        },
+      };
+      if let Some(GameEvent::Error(msg)) = result.get_events().iter().find(|e| matches!(e, GameEvent::Error(_))) {
+        exploration.failures.push(Failure { state: from, command: command.clone(), message: msg.clone() });
+      }
+      exploration.note_rooms(game, &state, &result);
+
+      if exploration.states.len() >= limits.max_states && !known.contains_key(&json::to_json(result.get_state())) {
+        exploration.truncated = true;
+        continue;
+      }
+      let (to, new) = exploration.add_state(result.to_state(), Some((from, command.clone())), &mut known);
+      if to == from {
+        continue;
+      }
+      exploration.transitions[from].push((command, to));
+      if new {
+        depths.push(depths[from] + 1);
+        queue.push_back(to);
+      }
+    }
+  }
+
+  exploration
+}
+
+// Where a walkthrough should lead to.
+pub enum Goal {
+  // Every ending, with one walkthrough for each ending room.
+  Endings,
+  Room(String),
+  // A state with the item, ie '$key$'. A room item is looked for in the room the player is in.
+  Item(GameItem),
+}
+
+impl Goal {
+  // Reads a goal given on the command line, either a room name or an item like '%bad%'.
+  pub fn parse(text: &str) -> Goal {
+    match playtest::parse_item(text) {
+      Some(item) => Goal::Item(item),
+      None => Goal::Room(text.to_string()),
+    }
+  }
+}
+
+pub struct Walkthrough {
+  pub commands: Vec<String>,
+  pub state: GameState,
+}
+
+impl Walkthrough {
+  // The walkthrough as a playtest (see playtest.rs) that checks it ends in the same room with
+  // the same items, so it can be saved next to the other playthroughs.
+  pub fn to_playtest(&self) -> String {
+    let mut text = String::new();
+    for command in self.commands.iter() {
+      text.push_str(&format!("> {}\n", command));
+    }
+    text.push_str(&format!("= {}\n", self.state.get_room_name()));
+    let inventory = &self.state.inventory;
+    for item in inventory.personal.iter() {
+      text.push_str(&format!("${}$\n", item));
+    }
+    if let Some(items) = inventory.room.get(self.state.get_room_name()) {
+      for item in items.iter() {
+        text.push_str(&format!("&{}&\n", item));
+      }
+    }
+    for item in inventory.global.iter() {
+      text.push_str(&format!("%{}%\n", item));
+    }
+    text
+  }
+}
+
+// The shortest walkthroughs that reach the goal, one per ending room for Goal::Endings and at
+// most one otherwise.
+pub fn walkthroughs(exploration: &Exploration, goal: &Goal) -> Vec<Walkthrough> {
+  let mut rooms_done = BTreeSet::new();
+  let mut found = Vec::new();
+  // States are in the order they were found, which is also the order of their path length.
+  for (i, state) in exploration.states.iter().enumerate() {
+    let reached = match goal {
+      Goal::Endings => exploration.is_ending[i] && rooms_done.insert(state.get_room_name().to_string()),
+      Goal::Room(room) => state.get_room_name() == room,
+      Goal::Item(item) => state.inventory.check_item(item, state.get_room_name()),
+    };
+    if !reached {
+      continue;
+    }
+    found.push(Walkthrough { commands: exploration.path_to(i), state: state.clone() });
+    if !matches!(goal, Goal::Endings) {
+      break;
+    }
+  }
+  found
+}
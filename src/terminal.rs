@@ -0,0 +1,249 @@
+// Renders the text of the story for the terminal, see print_events.
+//
+// The markup is the one the web frontend reads in printClass.js: '#' around highlighted nouns,
+// '~' around italic text and '@rrggbb ..@' around text in a 24 bit color. With ANSI escape
+// sequences they become bold, italic and colored text, without them the markup is left out.
+//
+// Every line of text in a section is a paragraph. They are wrapped to the width of the terminal
+// with a blank line between them, and a turn longer than the terminal is shown a page at a time.
+
+use std::io::{IsTerminal, Write};
+
+const RESET: &str = "\x1b[0m";
+// Moves to the start of the line above and clears it, to remove a prompt once it was answered.
+const CLEAR_LINE_ABOVE: &str = "\x1b[1A\x1b[2K";
+
+const DEFAULT_WIDTH: usize = 80;
+const DEFAULT_HEIGHT: usize = 24;
+
+const BREAK_PROMPT: &str = "(press enter)";
+const MORE_PROMPT: &str = "-- more --";
+
+pub struct Renderer {
+  ansi: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Style {
+  bold: bool,
+  italic: bool,
+  color: Option<u32>,
+}
+
+impl Style {
+  fn is_plain(&self) -> bool {
+    !self.bold && !self.italic && self.color.is_none()
+  }
+
+  fn to_ansi(self) -> String {
+    let mut codes = Vec::new();
+    if self.bold {
+      codes.push("1".to_string());
+    }
+    if self.italic {
+      codes.push("3".to_string());
+    }
+    if let Some(color) = self.color {
+      codes.push(format!("38;2;{};{};{}", (color >> 16) & 0xff, (color >> 8) & 0xff, color & 0xff));
+    }
+    format!("\x1b[{}m", codes.join(";"))
+  }
+}
+
+impl Renderer {
+  pub fn new(ansi: bool) -> Renderer {
+    Renderer { ansi }
+  }
+
+  // Escape sequences are only written to a terminal and never when NO_COLOR is set, see no-color.org.
+  pub fn detect() -> Renderer {
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    Renderer::new(std::io::stdout().is_terminal() && !no_color)
+  }
+
+  // The text of a text event, starting in the style the event was given. A color of 0 is the
+  // color of the terminal, any other is 0xrrggbb.
+  pub fn render(&self, text: &str, italic: bool, bold: bool, color: u32) -> String {
+    let base = Style {
+      bold,
+      italic,
+      color: if color == 0 { None } else { Some(color) },
+    };
+    let mut style = base;
+    let mut written = Style { bold: false, italic: false, color: None };
+    let mut rendered = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+      match ch {
+        '#' => style.bold = !style.bold,
+        '~' => style.italic = !style.italic,
+        '@' if style.color != base.color => style.color = base.color,
+        '@' => {
+          let code: String = chars.clone().take(6).collect();
+          match u32::from_str_radix(&code, 16) {
+            Ok(color) if code.len() == 6 => {
+              style.color = Some(color);
+              chars.nth(5);
+              // The space between the color and the text is only there to separate them.
+              if chars.peek() == Some(&' ') {
+                chars.next();
+              }
+            },
+            _ => rendered.push('@'),
+          }
+        },
+        ch => {
+          if self.ansi && style != written {
+            if !written.is_plain() {
+              rendered.push_str(RESET);
+            }
+            if !style.is_plain() {
+              rendered.push_str(&style.to_ansi());
+            }
+            written = style;
+          }
+          rendered.push(ch);
+        },
+      }
+    }
+    if self.ansi && !written.is_plain() {
+      rendered.push_str(RESET);
+    }
+    rendered
+  }
+}
+
+// The lines of a paragraph no longer than the width, except for words that are longer on their own.
+// Escape sequences take up no width.
+pub fn wrap(text: &str, width: usize) -> Vec<String> {
+  let mut lines = Vec::new();
+  let mut line = String::new();
+  let mut line_width = 0;
+  for word in text.split(' ').filter(|word| !word.is_empty()) {
+    let word_width = visible_width(word);
+    if line_width > 0 && line_width + 1 + word_width > width {
+      lines.push(std::mem::take(&mut line));
+      line_width = 0;
+    }
+    if line_width > 0 {
+      line.push(' ');
+      line_width += 1;
+    }
+    line.push_str(word);
+    line_width += word_width;
+  }
+  if !line.is_empty() {
+    lines.push(line);
+  }
+  lines
+}
+
+fn visible_width(text: &str) -> usize {
+  let mut width = 0;
+  let mut escaped = false;
+  for ch in text.chars() {
+    match ch {
+      '\x1b' => escaped = true,
+      'm' if escaped => escaped = false,
+      _ if escaped => (),
+      _ => width += 1,
+    }
+  }
+  width
+}
+
+// A size from the environment, ie COLUMNS, which shells set for the terminal they run in.
+fn env_size(name: &str, default: usize) -> usize {
+  match std::env::var(name).ok().and_then(|value| value.trim().parse::<usize>().ok()) {
+    Some(size) if size > 0 => size,
+    _ => default,
+  }
+}
+
+// Prints the paragraphs of the story, waiting for the player at breaks and between pages.
+pub struct Screen {
+  renderer: Renderer,
+  width: usize,
+  // The lines in a page, or None when the output is not read by someone who can press enter.
+  height: Option<usize>,
+  // The lines shown since the player last pressed enter.
+  lines: usize,
+  // Whether a paragraph was shown this turn, so that the next one needs a blank line before it.
+  started: bool,
+}
+
+impl Screen {
+  pub fn new(renderer: Renderer, width: usize, height: Option<usize>) -> Screen {
+    Screen { renderer, width, height, lines: 0, started: false }
+  }
+
+  // Pages are only shown when both stdin and stdout are a terminal, so that piped commands are
+  // not taken for presses of enter.
+  pub fn detect() -> Screen {
+    let interactive = std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
+    let height = if interactive { Some(env_size("LINES", DEFAULT_HEIGHT)) } else { None };
+    Screen::new(Renderer::detect(), env_size("COLUMNS", DEFAULT_WIDTH), height)
+  }
+
+  // A paragraph of the story in the style of its text event, see Renderer::render.
+  pub fn text(&mut self, text: &str, italic: bool, bold: bool, color: u32) -> Result<(), String> {
+    let rendered = self.renderer.render(text, italic, bold, color);
+    self.paragraph(&rendered)
+  }
+
+  pub fn paragraph(&mut self, text: &str) -> Result<(), String> {
+    let lines = wrap(text, self.width);
+    if lines.is_empty() {
+      return Ok(());
+    }
+    if self.started {
+      self.line("")?;
+    }
+    self.started = true;
+    for line in lines.iter() {
+      self.line(line)?;
+    }
+    Ok(())
+  }
+
+  // Waits for the player to press enter at a |BREAK|.
+  pub fn wait(&mut self) -> Result<(), String> {
+    self.prompt(BREAK_PROMPT)
+  }
+
+  // Starts a new turn once the player typed a command.
+  pub fn end_turn(&mut self) {
+    self.lines = 0;
+    self.started = false;
+  }
+
+  fn line(&mut self, line: &str) -> Result<(), String> {
+    if let Some(height) = self.height {
+      if self.lines + 1 >= height {
+        self.prompt(MORE_PROMPT)?;
+      }
+    }
+    println!("{}", line);
+    self.lines += 1;
+    Ok(())
+  }
+
+  fn prompt(&mut self, prompt: &str) -> Result<(), String> {
+    print!("{}", prompt);
+    if let Err(msg) = std::io::stdout().flush() {
+      return Err(msg.to_string());
+    }
+    let mut line = String::new();
+    if let Err(msg) = std::io::stdin().read_line(&mut line) {
+      return Err(msg.to_string());
+    }
+    match (self.height, self.renderer.ansi) {
+      (Some(_), true) => print!("{}", CLEAR_LINE_ABOVE),
+      (Some(_), false) => (),
+      // Nothing was echoed after the prompt when the input is not typed.
+      (None, _) => println!(),
+    }
+    self.lines = 0;
+    Ok(())
+  }
+}
@@ -0,0 +1,184 @@
+// Recording and replaying of the commands typed during a game.
+//
+// A transcript is a JSON lines file. The first line names the transcript version and the
+// fingerprint of the game it was recorded against, every line after it is one turn:
+//
+//   {"version":2,"game":"2df92dc9d4c91ee9"}
+//   {"command":null,"events":[..],"state":{..}}
+//   {"command":"examine glass","events":[..],"state":{..}}
+//
+// The first turn is the start of the game and has no command. The events and state use the
+// layout described in json.rs. A replay runs every command again through a Session and stops at
+// the first turn whose events or state are not the ones that were recorded.
+//
+// Version 1 transcripts were recorded before the state kept its audio, so the audio of the state
+// is left out when replaying them.
+
+use std::fs::File;
+use std::io::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use super::{Game, GameResult};
+use super::history::TurnHistory;
+use super::session::Session;
+
+// Bump whenever the layout of a turn changes, older versions must keep replaying.
+pub const TRANSCRIPT_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+  version: u32,
+  game: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Turn {
+  command: Option<String>,
+  events: Value,
+  state: Value,
+}
+
+impl Turn {
+  fn new(command: Option<&str>, result: &GameResult) -> Turn {
+    Turn {
+      command: command.map(|c| c.trim().to_string()),
+      events: serde_json::to_value(result.get_events()).expect("ICE: Failed to serialize events"),
+      state: serde_json::to_value(result.get_state()).expect("ICE: Failed to serialize the game state"),
+    }
+  }
+}
+
+// Writes each turn to the file as soon as it happens, so the transcript survives a crash.
+pub struct Recorder {
+  path: String,
+  file: File,
+}
+
+impl Recorder {
+  pub fn create(game: &Game, path: &str) -> Result<Recorder, String> {
+    let file = match File::create(path) {
+      Ok(f) => f,
+      Err(msg) => return Err(format!("Could not create {}: {}", path, msg)),
+    };
+    let mut recorder = Recorder {
+      path: path.to_string(),
+      file,
+    };
+    let header = Header {
+      version: TRANSCRIPT_VERSION,
+      game: game.fingerprint(),
+    };
+    recorder.write_line(&super::json::to_json(&header))?;
+    Ok(recorder)
+  }
+
+  pub fn record(&mut self, command: Option<&str>, result: &GameResult) -> Result<(), String> {
+    self.write_line(&super::json::to_json(&Turn::new(command, result)))
+  }
+
+  fn write_line(&mut self, line: &str) -> Result<(), String> {
+    match writeln!(self.file, "{}", line).and_then(|_| self.file.flush()) {
+      Ok(_) => Ok(()),
+      Err(msg) => Err(format!("Could not write to {}: {}", self.path, msg)),
+    }
+  }
+}
+
+pub struct Replay {
+  // How many turns matched the transcript, including the start of the game.
+  pub turns: usize,
+  pub fingerprint_matches: bool,
+  // A description of the first turn that did not match, if any.
+  pub difference: Option<String>,
+}
+
+fn describe_turn(number: usize, command: &Option<String>) -> String {
+  match command {
+    Some(c) => format!("Turn {} '{}'", number, c),
+    None => format!("Turn {} (start of the game)", number),
+  }
+}
+
+fn compare_turn(number: usize, expected: &Turn, found: &Turn) -> Option<String> {
+  let name = describe_turn(number, &expected.command);
+  if expected.events != found.events {
+    let expected_events = expected.events.as_array().cloned().unwrap_or_default();
+    let found_events = found.events.as_array().cloned().unwrap_or_default();
+    for i in 0..std::cmp::max(expected_events.len(), found_events.len()) {
+      match (expected_events.get(i), found_events.get(i)) {
+        (Some(e), Some(f)) if e == f => continue,
+        (e, f) => {
+          let show = |v: Option<&Value>| v.map(|v| v.to_string()).unwrap_or_else(|| "nothing".to_string());
+          return Some(format!("{}: event {} differs\n  expected: {}\n  found:    {}", name, i+1, show(e), show(f)));
+        },
+      }
+    }
+  }
+  if expected.state != found.state {
+    return Some(format!("{}: the game state differs\n  expected: {}\n  found:    {}", name, expected.state, found.state));
+  }
+  None
+}
+
+// Whether the command is the terminal's 'load [FILE]', the same way start_game reads it.
+fn is_load(command: &str) -> bool {
+  let words: Vec<&str> = command.split_whitespace().collect();
+  matches!(words.as_slice(), ["load"] | ["load", _])
+}
+
+pub fn replay(game: &Game, transcript: &str) -> Result<Replay, String> {
+  let mut lines = transcript.lines().filter(|l| !l.trim().is_empty());
+  let header: Header = match lines.next().map(serde_json::from_str) {
+    Some(Ok(header)) => header,
+    Some(Err(msg)) => return Err(format!("Invalid transcript header: {}", msg)),
+    None => return Err("The transcript is empty".to_string()),
+  };
+  if header.version > TRANSCRIPT_VERSION {
+    return Err(format!("The transcript is from a newer version of the game (transcript format {}, expected at most {})", header.version, TRANSCRIPT_VERSION));
+  }
+
+  let mut report = Replay {
+    turns: 0,
+    fingerprint_matches: header.game == game.fingerprint(),
+    difference: None,
+  };
+  let mut session = Session::new(game, TurnHistory::default());
+  for (i, line) in lines.enumerate() {
+    let expected: Turn = match serde_json::from_str(line) {
+      Ok(turn) => turn,
+      Err(msg) => return Err(format!("Invalid turn on line {} of the transcript: {}", i+2, msg)),
+    };
+    let mut found = match &expected.command {
+      None if i == 0 => Turn::new(None, session.get_result()),
+      None => return Err(format!("Turn {} of the transcript has no command", i+1)),
+      // A load cannot be run again, so the recorded state is taken as it is.
+      Some(command) if is_load(command) => {
+        let state = match serde_json::from_value(expected.state.clone()) {
+          Ok(state) => state,
+          Err(msg) => return Err(format!("Invalid state on line {} of the transcript: {}", i+2, msg)),
+        };
+        Turn::new(Some(command), session.restore(state))
+      },
+      Some(command) => Turn::new(Some(command), session.input(command)),
+    };
+
+    if header.version < 2 {
+      if let Some(state) = found.state.as_object_mut() {
+        state.remove("audio");
+      }
+    }
+    if let Some(difference) = compare_turn(i+1, &expected, &found) {
+      report.difference = Some(difference);
+      return Ok(report);
+    }
+    report.turns += 1;
+  }
+  Ok(report)
+}
+
+pub fn replay_file(game: &Game, path: &str) -> Result<Replay, String> {
+  match std::fs::read_to_string(path) {
+    Ok(text) => replay(game, &text),
+    Err(msg) => Err(format!("Could not read {}: {}", path, msg)),
+  }
+}
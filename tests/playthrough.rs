@@ -0,0 +1,47 @@
+// Plays every file in tests/playthroughs and every TEST section against the story in
+// www/narrative.txt, see src/playtest.rs for the layout of a playthrough.
+
+use std::fs;
+use std::path::PathBuf;
+
+fn compile_story() -> reflections::Game {
+  let root_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  let narrative = fs::read_to_string(root_path.join("www").join("narrative.txt")).expect("The story should be readable");
+  reflections::compile(narrative)
+}
+
+#[test]
+fn test_playthroughs() {
+  let root_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  let game = compile_story();
+
+  let mut paths: Vec<PathBuf> = fs::read_dir(root_path.join("tests").join("playthroughs"))
+    .expect("The playthroughs should be readable")
+    .map(|entry| entry.expect("The playthroughs should be readable").path())
+    .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+    .collect();
+  paths.sort();
+  assert!(!paths.is_empty(), "There are no playthroughs to run");
+
+  let mut failures = Vec::new();
+  for path in paths.iter() {
+    let path = path.to_str().expect("The path of a playthrough should be valid unicode");
+    match reflections::playtest::run_file(&game, path) {
+      Ok(playtest) => if let Some(failure) = playtest.failure {
+        failures.push(format!("{}\n{}", path, failure));
+      },
+      Err(msg) => failures.push(msg),
+    }
+  }
+  assert!(failures.is_empty(), "\n{}\n", failures.join("\n\n"));
+}
+
+#[test]
+fn test_script_tests() {
+  let game = compile_story();
+  let failures: Vec<String> = game.get_tests().iter()
+    .filter_map(|game_test| reflections::playtest::run_test(&game, game_test).failure
+      .map(|failure| format!("TEST |{}|\n{}", game_test.name.to_string(), failure)))
+    .collect();
+  assert!(failures.is_empty(), "\n{}\n", failures.join("\n\n"));
+}
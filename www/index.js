@@ -3,7 +3,21 @@ import * as wasm from "reflections";
 function init() {
   window.global.var.narrative = window.global.var.narrative.join(" ");
   window.global.var.game = wasm.compile(window.global.var.narrative);
+  // Visiting with '?captions' shows every sound as text instead of playing it.
+  if (new URLSearchParams(window.location.search).has("captions")) {
+    window.global.var.game.set_captions(true);
+  }
   window.global.var.gameResult = window.global.var.game.start();
+  let save = window.localStorage.getItem("reflections-save");
+  if (save !== null) {
+    try {
+      let state = window.global.var.game.load(save);
+      window.global.var.gameResult = window.global.var.game.resume(state);
+    } catch (e) {
+      console.log("Could not load the saved game: " + e);
+    }
+  }
+  window.global.var.history = new wasm.TurnHistory(50);
   let start_text = window.global.var.gameResult.to_string();
   window.global.var.displayString = new printClass(start_text, -900, -500, 1600);
 
@@ -49,6 +63,16 @@ function executeCommand()
     window.global.var.displayString = new printClass(window.global.var.gameResult.to_string(), -900, -500, 1600);
     return;
   }
+  if(command[0] == "undo" || command[0] == "redo") {
+    var current = window.global.var.gameResult.to_state();
+    var previous = command[0] == "undo" ? window.global.var.history.undo(current) : window.global.var.history.redo(current);
+    if (previous !== undefined) {
+      window.global.var.gameResult = window.global.var.game.resume_from(current, previous);
+      window.localStorage.setItem("reflections-save", window.global.var.game.save(window.global.var.gameResult.to_state()));
+      window.global.var.displayString = new printClass(window.global.var.gameResult.to_string(), -900, -500, 1600);
+    }
+    return;
+  }
   //shell.shell_line = "> ";
   var command_list = ["examine", "use", "talk", "go", "take", "help", "inventory"];
   var short_command_list = ["e", "u", "l", "g", "t", "h", "y", "n"];
@@ -99,7 +123,9 @@ function executeCommand()
     return_val = window.global.var.game.query("MISC", command[0], state);
   }
 
+  window.global.var.history.record(state, return_val);
   window.global.var.gameResult = return_val;
+  window.localStorage.setItem("reflections-save", window.global.var.game.save(return_val.to_state()));
   console.log("Query returned: " + return_val.to_string());
   //window.global.var.displayString = new printClass(return_val, -450, -250, 900);
   window.global.var.displayString = new printClass(return_val.to_string(), -900, -500, 1600);